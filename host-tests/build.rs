@@ -0,0 +1,10 @@
+// Feature groups of the firmware are plain cfgs here, modules are
+// tested with the default quad frame and receiver.
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rustc-check-cfg=cfg(motors, values(any()))");
+    println!("cargo:rustc-check-cfg=cfg(esc, values(any()))");
+    println!("cargo:rustc-check-cfg=cfg(receiver, values(any()))");
+    println!("cargo:rustc-cfg=motors=\"motors_quad\"");
+    println!("cargo:rustc-cfg=receiver=\"receiver_sbus\"");
+}
@@ -0,0 +1,83 @@
+// Firmware modules without hardware dependencies; `stm32f30x`
+// submodules are only built for the target.
+#![no_std]
+// firmware builds its statics with `const fn new()`, Default is not const
+#![allow(clippy::new_without_default)]
+
+// AHRS needs the MPU driver, only its result is used by the modules
+pub mod ahrs {
+    use crate::prelude::*;
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct AhrsResult {
+        pub accel: [f32; 3],
+        pub gyro: [f32; 3],
+        pub dt_s: f32,
+        pub ypr: EulerAngles,
+        pub biased_gyro: [f32; 3],
+    }
+
+    impl AhrsResult {
+        #[inline]
+        pub const fn new() -> Self {
+            AhrsResult {
+                accel: [0.0, 0.0, 0.0],
+                gyro: [0.0, 0.0, 0.0],
+                dt_s: 0.0,
+                ypr: EulerAngles {
+                    yaw: 0.0,
+                    pitch: 0.0,
+                    roll: 0.0,
+                },
+                biased_gyro: [0.0, 0.0, 0.0],
+            }
+        }
+    }
+}
+
+pub mod prelude {
+    #[derive(Debug, Clone, Copy)]
+    pub struct EulerAngles {
+        pub yaw: f32,
+        pub pitch: f32,
+        pub roll: f32,
+    }
+}
+
+pub mod communication {
+    use heapless::consts::*;
+    use heapless::Vec;
+
+    pub type TxBuffer = Vec<u8, U512>;
+}
+
+#[path = "../../src/battery.rs"]
+pub mod battery;
+#[path = "../../src/cmd.rs"]
+pub mod cmd;
+#[path = "../../src/crsf.rs"]
+pub mod crsf;
+#[path = "../../src/dshot.rs"]
+pub mod dshot;
+#[path = "../../src/failsafe.rs"]
+pub mod failsafe;
+#[path = "../../src/filters.rs"]
+pub mod filters;
+#[path = "../../src/mixer.rs"]
+pub mod mixer;
+#[path = "../../src/modes.rs"]
+pub mod modes;
+#[path = "../../src/motortest.rs"]
+pub mod motortest;
+#[path = "../../src/oneshot.rs"]
+pub mod oneshot;
+#[path = "../../src/ppm.rs"]
+pub mod ppm;
+#[path = "../../src/rc.rs"]
+pub mod rc;
+#[path = "../../src/sbus.rs"]
+pub mod sbus;
+#[path = "../../src/types.rs"]
+pub mod types;
+#[path = "../../src/utils.rs"]
+pub mod utils;
@@ -0,0 +1,193 @@
+use fcfs_host_tests::cmd::{self, Error, Reply};
+use fcfs_host_tests::rc::ChannelMap;
+use fcfs_host_tests::types::{Control, State};
+
+fn reply(line: &str, control: &mut Control, armed: bool) -> Reply {
+    let mut cmd = cmd::create();
+    let mut state = State::new();
+    state.armed = armed;
+    let mut reply = None;
+    for b in line.bytes().chain(Some(b'\n')) {
+        if let Some(r) = cmd.feed(b, control, &state) {
+            reply = Some(r);
+        }
+    }
+    reply.expect("no reply to complete line")
+}
+
+fn send(line: &str, control: &mut Control, armed: bool) -> Result<(), Error> {
+    reply(line, control, armed).result
+}
+
+fn echo(line: &str, control: &mut Control) -> String {
+    let reply = reply(line, control, false);
+    String::from_utf8(reply.echo.to_vec()).unwrap()
+}
+
+#[test]
+fn mixer_commands() {
+    let mut control = Control::new();
+    assert!(send("mix=hexp", &mut control, false).is_ok());
+    assert_eq!(control.mixer.geometry.motors, 6);
+    assert!(send("mixrow=2,0.5,-1,1,1", &mut control, false).is_ok());
+    assert_eq!(control.mixer.geometry.rows[2], [0.5, -1., 1., 1.]);
+    assert!(send("mixn=4", &mut control, false).is_ok());
+    assert_eq!(control.mixer.geometry.motors, 4);
+}
+
+#[test]
+fn mixer_count_in_range() {
+    let mut control = Control::new();
+    assert!(send("mixn=0", &mut control, false) == Err(Error::Malformed));
+    assert!(send("mixn=9", &mut control, false) == Err(Error::Malformed));
+    assert_eq!(control.mixer.geometry.motors, 4);
+}
+
+#[test]
+fn mixer_refused_while_armed() {
+    let mut control = Control::new();
+    let seq = control.link_seq;
+    for line in ["mix=hexx", "mixn=6", "mixrow=0,1,1,1,1"].iter() {
+        assert!(send(line, &mut control, true) == Err(Error::Armed));
+    }
+    assert_eq!(control.mixer.geometry.motors, 4);
+    assert_eq!(control.mixer.geometry.rows[0], [1., -1., -1., 1.]);
+    assert_eq!(control.link_seq, seq);
+    // other commands still apply in flight
+    assert!(send("pk=5", &mut control, true).is_ok());
+    assert_eq!(control.gains[0].p, 5.);
+}
+
+#[test]
+fn stick_setup_refused_while_armed() {
+    let mut control = Control::new();
+    assert!(send("rcmap=taer", &mut control, true) == Err(Error::Armed));
+    assert!(send("rccal=0,900,1400,1900", &mut control, true).is_err());
+    assert!(control.rc.map == ChannelMap::Aetr);
+    assert_eq!(control.rc.calibration[0].mid, 1500.);
+    assert!(send("rcmap=taer", &mut control, false).is_ok());
+    assert!(send("rccal=0,900,1400,1900", &mut control, false).is_ok());
+    assert!(control.rc.map == ChannelMap::Taer);
+    assert_eq!(control.rc.calibration[0].mid, 1400.);
+    // rates and expo may be tuned in flight
+    assert!(send("rcrate=300", &mut control, true).is_ok());
+    assert_eq!(control.rc.rates, [300.; 3]);
+}
+
+#[test]
+fn battery_units() {
+    let mut control = Control::new();
+    // scales in hundredths, offset and thresholds in millivolts
+    assert!(send("vscale=1100", &mut control, false).is_ok());
+    assert!(send("cscale=2500", &mut control, false).is_ok());
+    assert!(send("coffs=-120", &mut control, false).is_ok());
+    assert!(send("vwarn=3500", &mut control, false).is_ok());
+    assert!(send("vcrit=3300", &mut control, false).is_ok());
+    assert!(send("vnom=4200", &mut control, false).is_ok());
+    assert_eq!(control.battery.voltage_scale, 11.);
+    assert_eq!(control.battery.current_scale, 25.);
+    assert_eq!(control.battery.current_offset, -0.12);
+    assert_eq!(control.battery.warning_v, 3.5);
+    assert_eq!(control.battery.critical_v, 3.3);
+    assert_eq!(control.battery.nominal_v, 4.2);
+}
+
+#[test]
+fn bad_words_are_malformed() {
+    let mut control = Control::new();
+    for line in ["mix=quad", "dflt=pt2", "air=yes", "dir=up", "3d=1"].iter() {
+        assert!(send(line, &mut control, false) == Err(Error::Malformed));
+    }
+    assert!(send("vcomp=", &mut control, false) == Err(Error::Malformed));
+    assert!(send("mix=\"hexx\"", &mut control, false).is_ok());
+    assert_eq!(control.mixer.geometry.motors, 6);
+    assert!(send("air=off", &mut control, false).is_ok());
+    assert!(!control.mixer.airmode);
+    assert!(send("vcomp=on", &mut control, false).is_ok());
+    assert!(control.battery.compensation);
+}
+
+#[test]
+fn echo_applied_values() {
+    let mut control = Control::new();
+    assert_eq!(echo("rcexpo=150,20,-5", &mut control), "rcexpo=100,20,0");
+    assert_eq!(echo("rcdb=-5", &mut control), "rcdb=0");
+    assert_eq!(echo("pk=0.25", &mut control), "pk=0.25");
+    assert_eq!(echo("lto=500", &mut control), "lto=500");
+    control.props_off = true;
+    assert_eq!(echo("mt=1,10,60000", &mut control), "mt=1,10,10000");
+    assert_eq!(echo("mode=angle", &mut control), "mode=angle");
+    // long lines are not cut
+    let row = "mixrow=7,-0.70710677,0.70710677,-1,1";
+    assert_eq!(echo(row, &mut control), row);
+    let rc = "rc=1500,1500,1500,1000,2000,1000,2000,1000,1500,1500";
+    assert_eq!(echo(rc, &mut control), rc);
+    // failed commands echo what was sent
+    assert_eq!(echo("rcdb=x", &mut control), "rcdb=x");
+}
+
+#[test]
+fn non_finite_values_rejected() {
+    let mut control = Control::new();
+    let lines = ["pk=nan", "athr=NaN", "lto=inf", "rr=-inf", "pk=1e39"];
+    for line in lines.iter() {
+        assert!(send(line, &mut control, false) == Err(Error::Malformed));
+    }
+    assert_eq!(control.arm_thrust, 50.);
+    assert!(control.gains[0].p.is_finite());
+    let vectors = ["rcrate=nan", "rcexpo=10,inf,10", "mixrow=0,nan,1,1,1"];
+    for line in vectors.iter() {
+        assert!(send(line, &mut control, false) == Err(Error::Malformed));
+    }
+}
+
+#[test]
+fn physical_limits() {
+    let mut control = Control::new();
+    control.props_off = true;
+    let lines = [
+        "tthurst=-1",
+        "athr=-5",
+        "idle=101",
+        "tlin=-1",
+        "mt=0,120,500",
+    ];
+    for line in lines.iter() {
+        assert!(send(line, &mut control, false) == Err(Error::Malformed));
+    }
+    assert!(send("maxout=100", &mut control, false).is_ok());
+    assert!(send("tthurst=0", &mut control, false).is_ok());
+}
+
+#[test]
+fn invalid_utf8_is_malformed() {
+    let mut control = Control::new();
+    let mut cmd = cmd::create();
+    let state = State::new();
+    let mut reply = None;
+    for b in b"mode=\xffangle\xc3\n".iter() {
+        if let Some(r) = cmd.feed(*b, &mut control, &state) {
+            reply = Some(r);
+        }
+    }
+    assert!(reply.unwrap().result == Err(Error::Malformed));
+}
+
+#[test]
+fn motor_tests_need_props_off() {
+    let mut control = Control::new();
+    for line in ["mt=0,10,500", "esccal", "escdir"].iter() {
+        assert!(send(line, &mut control, false) == Err(Error::Props));
+        assert!(control.motor_test.is_none());
+    }
+    control.props_off = true;
+    for line in ["mt=0,10,500", "esccal", "escdir"].iter() {
+        assert!(send(line, &mut control, true) == Err(Error::Armed));
+        assert!(control.motor_test.is_none());
+        assert!(send(line, &mut control, false).is_ok());
+        assert!(control.motor_test.is_some());
+        control.motor_test = None;
+    }
+    // typo is still unknown, not refused
+    assert!(send("esccalx", &mut control, true) == Err(Error::Unknown));
+}
@@ -0,0 +1,142 @@
+use fcfs_host_tests::crsf::{self, Crsf, Downlink};
+use fcfs_host_tests::rc::Input;
+use fcfs_host_tests::types::{Control, Failsafe, State};
+
+const ADDRESS_FC: u8 = 0xC8;
+const TYPE_LINK_STATISTICS: u8 = 0x14;
+const TYPE_RC_CHANNELS: u8 = 0x16;
+
+// 16 channels of 11 bits, LSB first
+fn pack(channels: &[u16; 16]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut bits = 0u32;
+    let mut count = 0;
+    for channel in channels.iter() {
+        bits |= (*channel as u32 & 0x07FF) << count;
+        count += 11;
+        while count >= 8 {
+            data.push(bits as u8);
+            bits >>= 8;
+            count -= 8;
+        }
+    }
+    data
+}
+
+fn frame(frame_type: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![ADDRESS_FC, payload.len() as u8 + 2, frame_type];
+    frame.extend_from_slice(payload);
+    frame.push(crsf::crc8(&frame[2..]));
+    frame
+}
+
+// Last input returned while feeding all bytes
+fn feed(crsf: &mut Crsf, bytes: &[u8]) -> Option<Input> {
+    bytes.iter().fold(None, |last, b| crsf.feed(*b).or(last))
+}
+
+#[test]
+fn crc8_dvb_s2_vectors() {
+    assert_eq!(crsf::crc8(b""), 0x00);
+    assert_eq!(crsf::crc8(b"123456789"), 0xBC);
+    assert_eq!(crsf::crc8(&[0x01]), 0xD5);
+}
+
+#[test]
+fn rc_channels_decoded() {
+    let mut channels = [992u16; 16];
+    channels[0] = 172;
+    channels[1] = 1811;
+    channels[15] = 1500;
+    let payload = pack(&channels);
+    assert_eq!(payload.len(), 22);
+    let mut crsf = crsf::create();
+    let input = feed(&mut crsf, &frame(TYPE_RC_CHANNELS, &payload)).unwrap();
+    assert_eq!(input.count, 16);
+    assert_eq!(input.channels[0], 987);
+    assert_eq!(input.channels[1], 2011);
+    assert_eq!(input.channels[2], 1500);
+    assert_eq!(input.channels[15], 1817);
+    assert_eq!(input.link_quality, 100);
+}
+
+#[test]
+fn link_statistics_set_quality() {
+    let mut crsf = crsf::create();
+    // rssi 1 and 2, link quality, snr, antenna, rf mode, tx power,
+    // then downlink rssi, quality and snr
+    let stats = [50, 60, 73, 10, 0, 2, 1, 40, 100, 8];
+    let frame_stats = frame(TYPE_LINK_STATISTICS, &stats);
+    assert!(feed(&mut crsf, &frame_stats).is_none());
+    let rc = frame(TYPE_RC_CHANNELS, &pack(&[992; 16]));
+    assert_eq!(feed(&mut crsf, &rc).unwrap().link_quality, 73);
+}
+
+#[test]
+fn bad_frames_rejected() {
+    let mut crsf = crsf::create();
+    let good = frame(TYPE_RC_CHANNELS, &pack(&[992; 16]));
+    let mut bad_crc = good.clone();
+    *bad_crc.last_mut().unwrap() ^= 0x01;
+    assert!(feed(&mut crsf, &bad_crc).is_none());
+    let mut corrupted = good.clone();
+    corrupted[10] ^= 0x40;
+    assert!(feed(&mut crsf, &corrupted).is_none());
+    // length below type and crc, or beyond buffer
+    assert!(feed(&mut crsf, &[ADDRESS_FC, 1]).is_none());
+    assert!(feed(&mut crsf, &[ADDRESS_FC, 63]).is_none());
+    // short channels frame
+    let short = frame(TYPE_RC_CHANNELS, &[0; 10]);
+    assert!(feed(&mut crsf, &short).is_none());
+    // garbage before address is skipped
+    let mut noisy = vec![0x00, 0x55, 0xFF];
+    noisy.extend_from_slice(&good);
+    assert!(feed(&mut crsf, &noisy).is_some());
+}
+
+#[test]
+fn downlink_round_trip() {
+    let mut state = State::new();
+    state.ahrs.ypr.pitch = 0.5;
+    state.ahrs.ypr.roll = -0.25;
+    state.ahrs.ypr.yaw = 3.;
+    state.battery.voltage = 16.8;
+    state.battery.current = 12.3;
+    state.battery.mah = 1234.;
+    let control = Control::new();
+    let mut out = [0; crsf::MAX_FRAME];
+    let mut kind = Downlink::Attitude;
+    for _ in 0..3 {
+        let len = crsf::downlink(kind, &state, &control, &mut out);
+        let frame = &out[..len];
+        assert_eq!(frame[0], ADDRESS_FC);
+        assert_eq!(frame[1] as usize, len - 2);
+        assert_eq!(crsf::crc8(&frame[2..len - 1]), frame[len - 1]);
+        let payload = &frame[3..len - 1];
+        let be16 = |i: usize| u16::from_be_bytes([payload[i], payload[i + 1]]);
+        match kind {
+            Downlink::Attitude => {
+                assert_eq!(frame[2], 0x1E);
+                assert_eq!(be16(0) as i16, 5000);
+                assert_eq!(be16(2) as i16, -2500);
+                assert_eq!(be16(4) as i16, 30000);
+            }
+            Downlink::Battery => {
+                assert_eq!(frame[2], 0x08);
+                assert_eq!(be16(0), 168);
+                assert_eq!(be16(2), 123);
+                assert_eq!(&payload[4..7], &[0x00, 0x04, 0xD2]);
+            }
+            Downlink::FlightMode => {
+                assert_eq!(frame[2], 0x21);
+                assert_eq!(payload, b"ANGL*\0");
+            }
+        }
+        kind = kind.next();
+    }
+    assert!(kind == Downlink::Attitude);
+    state.armed = true;
+    state.failsafe = Failsafe::Level;
+    let len = crsf::downlink(Downlink::FlightMode, &state, &control, &mut out);
+    assert_eq!(&out[3..len - 1], b"!FS!\0");
+}
@@ -0,0 +1,134 @@
+use fcfs_host_tests::dshot::*;
+
+// xor of all four nibbles, zero for valid frame
+fn nibbles(frame: u16) -> u16 {
+    (frame ^ (frame >> 4) ^ (frame >> 8) ^ (frame >> 12)) & 0x0F
+}
+
+#[test]
+fn frame_layout() {
+    // 1046 without telemetry, example from protocol description
+    assert_eq!(frame(1046, false, false), 0b1000_0010_1100_0110);
+    assert_eq!(frame(1046, true, false), 0b1000_0010_1101_0111);
+    // bidirectional inverts crc only
+    assert_eq!(frame(1046, false, true), 0b1000_0010_1100_1001);
+    assert_eq!(frame(Command::MotorStop as u16, false, false), 0);
+}
+
+#[test]
+fn crc_over_ranges() {
+    // commands, then 3D reverse and forward throttle around neutral
+    let ranges = [(1, 47), (48, 1047), (1048, 1048), (1049, 2047)];
+    for (lo, hi) in ranges.iter() {
+        for value in *lo..=*hi {
+            for telemetry in [false, true].iter() {
+                let packet = (value << 1) | *telemetry as u16;
+                let normal = frame(value, *telemetry, false);
+                let bidir = frame(value, *telemetry, true);
+                assert_eq!(normal >> 4, packet);
+                assert_eq!(bidir >> 4, packet);
+                assert_eq!(nibbles(normal), 0, "value {}", value);
+                assert_eq!(nibbles(bidir), 0x0F, "value {}", value);
+            }
+        }
+    }
+}
+
+#[test]
+fn throttle_range() {
+    assert_eq!(throttle(0., 2000.), Command::MotorStop as u16);
+    assert_eq!(throttle(-5., 2000.), Command::MotorStop as u16);
+    // any positive duty spins the motor
+    assert_eq!(throttle(0.01, 2000.), MIN_THROTTLE);
+    assert_eq!(throttle(1000., 2000.), 1047);
+    assert_eq!(throttle(2000., 2000.), MAX_THROTTLE);
+    assert_eq!(throttle(3000., 2000.), MAX_THROTTLE);
+}
+
+#[test]
+fn encode_timings() {
+    let timing = Timing::new(120);
+    // high for 3/8 of bit period for zero, 3/4 for one
+    assert_eq!(timing.bit0, 45);
+    assert_eq!(timing.bit1, 90);
+    let channels = 4;
+    let mut buffer = [0u32; BITS * 4];
+    let value = frame(1046, false, false);
+    encode(value, timing, &mut buffer, 2, channels);
+    for bit in 0..BITS {
+        let one = value & (0x8000 >> bit) != 0;
+        let expected = if one { timing.bit1 } else { timing.bit0 };
+        assert_eq!(buffer[bit * channels + 2], expected, "bit {}", bit);
+        for other in [0, 1, 3].iter() {
+            assert_eq!(buffer[bit * channels + other], 0);
+        }
+    }
+}
+
+#[rustfmt::skip]
+const GCR_ENCODE: [u32; 16] = [
+    0x19, 0x1B, 0x12, 0x13, 0x1D, 0x15, 0x16, 0x17,
+    0x1A, 0x09, 0x0A, 0x0B, 0x1E, 0x0D, 0x0E, 0x0F,
+];
+
+// Line levels of reply with 12 bit payload: start bit, then GCR
+// quintets where one is a transition
+fn reply_levels(payload: u16) -> u32 {
+    let crc = !(payload ^ (payload >> 4) ^ (payload >> 8)) & 0x0F;
+    let value = (payload << 4) | crc;
+    let gcr = (0..4).rev().fold(0u32, |g, nibble| {
+        (g << 5) | GCR_ENCODE[((value >> (nibble * 4)) & 0x0F) as usize]
+    });
+    let mut bits = 0u32;
+    for bit in (0..20).rev() {
+        let next = (bits >> (bit + 1)) & 1;
+        bits |= (next ^ ((gcr >> bit) & 1)) << bit;
+    }
+    bits
+}
+
+#[test]
+fn decode_reply_payload() {
+    for payload in [0x0FFF, 0x0000, 0x03E8, 0x0A5A, 0x0123].iter() {
+        let bits = reply_levels(*payload);
+        assert_eq!(decode_reply(bits), Some(*payload), "{:03x}", payload);
+    }
+}
+
+#[test]
+fn decode_reply_rejects() {
+    let bits = reply_levels(0x03E8);
+    // any single flipped level breaks GCR or CRC
+    for bit in 0..20 {
+        assert_eq!(decode_reply(bits ^ (1 << bit)), None, "bit {}", bit);
+    }
+    // no transitions decode to invalid quintets
+    assert_eq!(decode_reply(0), None);
+}
+
+#[test]
+fn reply_bits_from_samples() {
+    let pin = 3;
+    let bits = reply_levels(0x0A5A);
+    let mut samples = vec![1u16 << pin; 10];
+    for bit in (0..REPLY_BITS).rev() {
+        let level = ((bits >> bit) & 1) as u16;
+        samples.extend_from_slice(&[level << pin; OVERSAMPLE]);
+    }
+    samples.extend_from_slice(&[1 << pin; 10]);
+    assert_eq!(reply_bits(&samples, pin), Some(bits));
+    assert_eq!(reply_bits(&samples, 2), None);
+    // capture cut in the middle of reply
+    assert_eq!(reply_bits(&samples[..40], pin), None);
+}
+
+#[test]
+fn erpm_from_period() {
+    // stopped motor
+    assert_eq!(erpm(0x0FFF), 0.);
+    assert_eq!(erpm(0), 0.);
+    // 250 << 1 us period
+    assert_eq!(erpm((1 << 9) | 250), 120_000.);
+    assert_eq!(erpm(500), 120_000.);
+    assert_eq!(erpm((7 << 9) | 1), 60_000_000. / 128.);
+}
@@ -0,0 +1,77 @@
+use fcfs_host_tests::battery::Status;
+use fcfs_host_tests::failsafe;
+use fcfs_host_tests::types::{Control, Failsafe, State};
+
+const DT_S: f32 = 0.004;
+
+fn flying(battery: Status) -> (State, Control) {
+    let mut state = State::new();
+    let mut control = Control::new();
+    state.ahrs.dt_s = DT_S;
+    state.armed = true;
+    state.battery.status = battery;
+    control.arm = true;
+    control.thrust = 1000.;
+    (state, control)
+}
+
+// Runs control loop with live link for `time_s`
+fn fly(state: &mut State, control: &mut Control, time_s: f32) {
+    for _ in 0..(time_s / DT_S) as usize {
+        control.link_seq = control.link_seq.wrapping_add(1);
+        failsafe::update(state, control);
+    }
+}
+
+#[test]
+fn critical_battery_descends() {
+    let (mut state, mut control) = flying(Status::Critical);
+    let transition = failsafe::update(&mut state, &mut control);
+    assert!(transition == Some(Failsafe::Descend));
+    assert!(control.arm);
+    let half_s = control.fs_descent_time / 2.;
+    fly(&mut state, &mut control, half_s);
+    assert!(state.failsafe == Failsafe::Descend);
+    assert!(control.arm);
+    assert!(control.thrust > 0. && control.thrust < 1000.);
+    // disarmed only once descent is over
+    fly(&mut state, &mut control, half_s + 0.1);
+    assert!(state.failsafe == Failsafe::Landed);
+    assert!(!control.arm);
+    assert_eq!(control.thrust, 0.);
+}
+
+#[test]
+fn critical_battery_cuts_leveling_short() {
+    let (mut state, mut control) = flying(Status::Ok);
+    control.failsafe_switch = true;
+    failsafe::update(&mut state, &mut control);
+    assert!(state.failsafe == Failsafe::Level);
+    state.battery.status = Status::Critical;
+    failsafe::update(&mut state, &mut control);
+    assert!(state.failsafe == Failsafe::Descend);
+    assert!(control.arm);
+}
+
+#[test]
+fn critical_battery_on_ground() {
+    let (mut state, mut control) = flying(Status::Critical);
+    state.armed = false;
+    control.arm = false;
+    assert!(failsafe::update(&mut state, &mut control).is_none());
+    assert!(state.failsafe == Failsafe::Ok);
+}
+
+#[test]
+fn battery_warning_only_reported() {
+    let (mut state, mut control) = flying(Status::Warning);
+    assert!(failsafe::update(&mut state, &mut control).is_none());
+    fly(&mut state, &mut control, 1.);
+    assert!(state.failsafe == Failsafe::Ok);
+    assert!(control.arm);
+    assert_eq!(control.thrust, 1000.);
+    // link loss with warning still levels first
+    control.failsafe_switch = true;
+    failsafe::update(&mut state, &mut control);
+    assert!(state.failsafe == Failsafe::Level);
+}
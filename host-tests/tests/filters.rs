@@ -0,0 +1,72 @@
+use fcfs_host_tests::filters::*;
+
+const DT_S: f32 = 0.004;
+const POLES: f32 = 14.;
+
+// eRPM of motor spinning at `hz`
+fn erpm(hz: f32) -> f32 {
+    hz * (POLES / 2.) * 60.
+}
+
+// Peak of gyro signal at `hz` on all axes after filter settles
+fn peak(filter: &mut RpmFilter, erpm: &[f32], hz: f32) -> f32 {
+    let mut peak = 0f32;
+    for i in 0..2000 {
+        let t = i as f32 * DT_S;
+        let x = (2. * core::f32::consts::PI * hz * t).sin();
+        let y = filter.apply([x; 3], erpm, POLES, 5., 100., DT_S);
+        if i > 1500 {
+            peak = peak.max(y[0].abs());
+        }
+    }
+    peak
+}
+
+#[test]
+fn stale_erpm_is_dropped() {
+    let mut filter = RpmFilter::new();
+    let mut value = 0.;
+    filter.track(0, Some(50_000.), &mut value);
+    assert_eq!(value, 50_000.);
+    for _ in 0..RPM_MAX_MISSED {
+        filter.track(0, None, &mut value);
+        assert_eq!(value, 50_000.);
+    }
+    filter.track(0, None, &mut value);
+    assert_eq!(value, 0.);
+    filter.track(0, Some(40_000.), &mut value);
+    assert_eq!(value, 40_000.);
+}
+
+#[test]
+fn notch_below_nyquist() {
+    let mut filter = RpmFilter::new();
+    assert!(peak(&mut filter, &[erpm(110.)], 110.) < 0.05);
+}
+
+#[test]
+fn notch_follows_alias_of_fast_motors() {
+    // 300Hz motor sampled at 250Hz looks like 50Hz tone
+    let mut filter = RpmFilter::new();
+    assert!(peak(&mut filter, &[erpm(300.)], 300.) < 0.05);
+    // nothing is parked near nyquist
+    let mut clamped = RpmFilter::new();
+    assert!(peak(&mut clamped, &[erpm(300.)], 112.5) > 0.9);
+}
+
+#[test]
+fn alias_near_zero_is_skipped() {
+    // 249Hz motor folds to 1Hz, where notch would eat stick input
+    let mut filter = RpmFilter::new();
+    let input = [0.1, 0.2, 0.3];
+    let output = filter.apply(input, &[erpm(249.)], POLES, 5., 100., DT_S);
+    assert_eq!(output, input);
+}
+
+#[test]
+fn slow_motors_are_skipped() {
+    let mut filter = RpmFilter::new();
+    let input = [0.1, 0.2, 0.3];
+    let output = filter.apply(input, &[erpm(50.)], POLES, 5., 100., DT_S);
+    assert_eq!(output, input);
+}
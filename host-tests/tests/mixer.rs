@@ -0,0 +1,123 @@
+use fcfs_host_tests::mixer::*;
+
+struct Fake {
+    duty: [f32; 6],
+    flushed: usize,
+}
+
+impl Fake {
+    fn new() -> Self {
+        Fake {
+            duty: [-1.; 6],
+            flushed: 0,
+        }
+    }
+}
+
+impl Outputs for Fake {
+    fn count(&self) -> usize {
+        6
+    }
+
+    fn max_duty(&self) -> f32 {
+        2000.
+    }
+
+    fn set(&mut self, index: usize, duty: f32) {
+        self.duty[index] = duty;
+    }
+
+    fn flush(&mut self) {
+        self.flushed += 1;
+    }
+}
+
+#[test]
+fn quad_x_attitude() {
+    let mut mixer = Mixer::new(Fake::new());
+    mixer.set_duty(0., 0., 0., 1000.);
+    assert_eq!(mixer.out.duty, [1000., 1000., 1000., 1000., 0., 0.]);
+    assert_eq!(mixer.out.flushed, 1);
+    // roll right speeds up right side
+    mixer.set_duty(100., 0., 0., 1000.);
+    assert_eq!(mixer.out.duty, [1100., 1100., 900., 900., 0., 0.]);
+    // pitch up speeds up front
+    mixer.set_duty(0., 100., 0., 1000.);
+    assert_eq!(mixer.out.duty, [900., 1100., 900., 1100., 0., 0.]);
+    mixer.set_duty(0., 0., 100., 1000.);
+    assert_eq!(mixer.out.duty, [900., 1100., 1100., 900., 0., 0.]);
+    assert!(!mixer.saturated());
+}
+
+#[test]
+fn presets_are_balanced() {
+    let presets = [
+        Preset::QuadX,
+        Preset::QuadPlus,
+        Preset::HexX,
+        Preset::HexPlus,
+        Preset::Y6,
+        Preset::OctoX,
+    ];
+    for preset in presets.iter() {
+        let geometry = Geometry::preset(*preset);
+        let rows = &geometry.rows[..geometry.motors];
+        for axis in 0..3 {
+            let sum: f32 = rows.iter().map(|r| r[axis]).sum();
+            assert!(sum.abs() < 1e-2, "axis {} sums to {}", axis, sum);
+        }
+        assert!(rows.iter().all(|r| r[3] == 1.));
+    }
+}
+
+#[test]
+fn configure_checks_outputs() {
+    let mut mixer = Mixer::new(Fake::new());
+    let mut config = Config::new();
+    config.use_preset(Preset::HexX);
+    assert!(mixer.configure(&config));
+    config.use_preset(Preset::OctoX);
+    assert!(!mixer.configure(&config));
+}
+
+#[test]
+fn saturation_keeps_corrections() {
+    let mut mixer = Mixer::new(Fake::new());
+    // near full thrust, thrust gives way to roll
+    mixer.set_duty(100., 0., 0., 1950.);
+    assert_eq!(mixer.out.duty[..4], [2000., 2000., 1800., 1800.]);
+    assert!(mixer.saturated());
+    // at zero thrust without airmode motors do not spin up
+    mixer.set_duty(100., 0., 0., 0.);
+    assert_eq!(mixer.out.duty[..4], [0.; 4]);
+    // airmode adds thrust to keep authority
+    let mut config = Config::new();
+    config.airmode = true;
+    mixer.configure(&config);
+    mixer.set_duty(100., 0., 0., 0.);
+    assert_eq!(mixer.out.duty[..4], [200., 200., 0., 0.]);
+}
+
+#[test]
+fn desaturate_scales() {
+    assert_eq!(desaturate(&[-100., 100.], 1000., 2000., false), (1., 1000.));
+    assert_eq!(desaturate(&[-100., 100.], 1950., 2000., false), (1., 1900.));
+    assert_eq!(desaturate(&[-100., 100.], 0., 2000., false), (0., 0.));
+    assert_eq!(desaturate(&[-100., 100.], 0., 2000., true), (1., 100.));
+    assert_eq!(
+        desaturate(&[-2000., 2000.], 1000., 2000., true),
+        (0.5, 1000.)
+    );
+}
+
+#[test]
+fn shape_inverts_thrust_curve() {
+    let mut config = Config::new();
+    assert_eq!(shape(0.5, &config), 0.5);
+    config.thrust_linear = 1.;
+    assert!((shape(0.25, &config) - 0.5).abs() < 1e-6);
+    config.thrust_linear = 0.;
+    config.idle = 0.1;
+    assert_eq!(shape(0., &config), 0.1);
+    assert_eq!(shape(2., &config), 1.);
+}
@@ -0,0 +1,121 @@
+use fcfs_host_tests::cmd::{self, Error};
+use fcfs_host_tests::modes::{self, Action, Range};
+use fcfs_host_tests::rc::Input;
+use fcfs_host_tests::types::{Control, FlightMode, State};
+
+// Receiver input with AUX 0 to 2 at given widths
+fn aux(control: &mut Control, values: [u16; 3]) {
+    let mut input = Input::new();
+    input.count = 8;
+    input.channels[4..7].copy_from_slice(&values);
+    control.rc_input = input;
+    modes::update(&State::new(), control);
+}
+
+fn range(action: Action, aux: usize, min: u16, max: u16) -> Option<Range> {
+    Some(Range {
+        action,
+        aux,
+        min,
+        max,
+    })
+}
+
+fn configured() -> Control {
+    let mut control = Control::new();
+    control.ranges[0] = range(Action::Arm, 0, 1700, 2100);
+    control.ranges[1] = range(Action::Angle, 1, 1400, 1600);
+    control.ranges[2] = range(Action::Horizon, 1, 1700, 2100);
+    control.ranges[3] = range(Action::Failsafe, 2, 1900, 2100);
+    control
+}
+
+#[test]
+fn switches_set_modes() {
+    let mut control = configured();
+    aux(&mut control, [1000, 1000, 1000]);
+    assert!(!control.arm && control.mode == FlightMode::Acro);
+    aux(&mut control, [1000, 1500, 1000]);
+    assert!(control.mode == FlightMode::Angle);
+    aux(&mut control, [2000, 2000, 1000]);
+    assert!(control.arm && control.mode == FlightMode::Horizon);
+    assert!(!control.failsafe_switch);
+    aux(&mut control, [2000, 2000, 2000]);
+    assert!(control.arm && control.failsafe_switch);
+    aux(&mut control, [1000, 2000, 1000]);
+    assert!(!control.arm);
+}
+
+#[test]
+fn arm_switch_on_at_boot() {
+    let mut control = configured();
+    for _ in 0..3 {
+        aux(&mut control, [2000, 1000, 1000]);
+        assert!(!control.arm);
+    }
+    aux(&mut control, [1000, 1000, 1000]);
+    aux(&mut control, [2000, 1000, 1000]);
+    assert!(control.arm);
+}
+
+#[test]
+fn arm_range_set_while_on() {
+    let mut control = Control::new();
+    // slot was left by its previous range
+    control.ranges[0] = range(Action::Beeper, 0, 1700, 2100);
+    aux(&mut control, [1000, 1000, 1000]);
+    aux(&mut control, [2000, 1000, 1000]);
+    let mut cmd = cmd::create();
+    for b in b"range=0,arm,0,1700,2100\n".iter() {
+        cmd.feed(*b, &mut control, &State::new());
+    }
+    aux(&mut control, [2000, 1000, 1000]);
+    assert!(!control.arm);
+    aux(&mut control, [1000, 1000, 1000]);
+    aux(&mut control, [2000, 1000, 1000]);
+    assert!(control.arm);
+}
+
+#[test]
+fn no_rearm_after_failsafe() {
+    let mut control = configured();
+    aux(&mut control, [1000, 1000, 1000]);
+    aux(&mut control, [2000, 1000, 1000]);
+    assert!(control.arm);
+    // failsafe landed while switch stayed on
+    control.arm = false;
+    aux(&mut control, [2000, 1000, 1000]);
+    assert!(!control.arm);
+}
+
+fn send(line: &str, control: &mut Control) -> Result<(), Error> {
+    let mut cmd = cmd::create();
+    let mut result = None;
+    for b in line.bytes().chain(Some(b'\n')) {
+        if let Some(reply) = cmd.feed(b, control, &State::new()) {
+            result = Some(reply.result);
+        }
+    }
+    result.unwrap()
+}
+
+#[test]
+fn range_values_are_integers() {
+    let mut control = Control::new();
+    let lines = [
+        "range=0,arm,0.7,1800,2100",
+        "range=0,arm,0,-5,70000",
+        "range=0,arm,0,1800,1700",
+        "range=0,arm,12,1800,2100",
+        "range=0,arm,0,1800,2100,5",
+    ];
+    for line in lines.iter() {
+        let result = send(line, &mut control);
+        assert!(result == Err(Error::Malformed), "{}", line);
+    }
+    assert!(control.ranges[0].is_none());
+    assert!(send("range=0,arm,11,1800,2100", &mut control).is_ok());
+    let set = control.ranges[0].unwrap();
+    assert!(set.action == Action::Arm && set.aux == 11);
+    assert_eq!((set.min, set.max), (1800, 2100));
+}
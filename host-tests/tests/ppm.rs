@@ -0,0 +1,75 @@
+use fcfs_host_tests::ppm::{self, Ppm};
+use fcfs_host_tests::rc::Input;
+
+const SYNC: u16 = 5000;
+
+// Feeds rising edges spaced by `widths` starting at `start`,
+// returns last input and time of last edge
+fn feed(ppm: &mut Ppm, start: u16, widths: &[u16]) -> (Option<Input>, u16) {
+    let mut time = start;
+    let mut input = None;
+    for width in widths.iter() {
+        time = time.wrapping_add(*width);
+        if let Some(i) = ppm.feed(time) {
+            input = Some(i);
+        }
+    }
+    (input, time)
+}
+
+fn channels(input: &Input) -> &[u16] {
+    &input.channels[..input.count]
+}
+
+#[test]
+fn frame_needs_sync() {
+    let mut ppm = ppm::create();
+    // edges before first sync gap are not aligned to channels
+    let (input, time) = feed(&mut ppm, 0, &[1500, 1500, 1500, 1500, SYNC]);
+    assert!(input.is_none());
+    let frame = [1000, 1100, 1200, 1300, 1400, 1500, 1600, 1700, SYNC];
+    let (input, _) = feed(&mut ppm, time, &frame);
+    let input = input.expect("frame after sync");
+    assert_eq!(channels(&input), &frame[..8]);
+}
+
+#[test]
+fn short_frames() {
+    let mut ppm = ppm::create();
+    let (_, time) = feed(&mut ppm, 0, &[SYNC]);
+    let (input, time) = feed(&mut ppm, time, &[1000, 1500, 2000, 1200, SYNC]);
+    assert_eq!(channels(&input.unwrap()), &[1000, 1500, 2000, 1200]);
+    // attitude and thrust need four channels
+    let (input, _) = feed(&mut ppm, time, &[1000, 1500, 2000, SYNC]);
+    assert!(input.is_none());
+}
+
+#[test]
+fn glitch_drops_frame() {
+    let mut ppm = ppm::create();
+    let (_, time) = feed(&mut ppm, 0, &[SYNC]);
+    // noise spike splits a channel into short pulses
+    let glitched = [1500, 300, 1200, 1500, 1500, SYNC];
+    let (input, time) = feed(&mut ppm, time, &glitched);
+    assert!(input.is_none());
+    let (input, time) = feed(&mut ppm, time, &[1500, 2400, 1500, 1500, SYNC]);
+    assert!(input.is_none());
+    // too many channels
+    let long = [1500; 9];
+    let (input, time) = feed(&mut ppm, time, &long);
+    assert!(input.is_none());
+    let (input, _) =
+        feed(&mut ppm, time, &[SYNC, 1500, 1500, 1500, 1500, SYNC]);
+    assert_eq!(channels(&input.unwrap()), &[1500; 4]);
+}
+
+#[test]
+fn capture_wraps_around() {
+    let mut ppm = ppm::create();
+    let (_, time) = feed(&mut ppm, 0xFFFF - SYNC - 2000, &[SYNC]);
+    let frame = [1800, 1100, 1500, 1500, 1900, SYNC];
+    let (input, time) = feed(&mut ppm, time, &frame);
+    // timer overflowed within the frame
+    assert!(time < 0x8000);
+    assert_eq!(channels(&input.unwrap()), &frame[..5]);
+}
@@ -0,0 +1,109 @@
+use fcfs_host_tests::cmd;
+use fcfs_host_tests::rc::*;
+use fcfs_host_tests::types::{Control, State};
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() < 1e-5
+}
+
+#[test]
+fn deadband_edge() {
+    let cal = Calibration::new();
+    assert_eq!(normalize(1500., &cal, 5.), 0.);
+    // edge of deadband is still center
+    assert_eq!(normalize(1505., &cal, 5.), 0.);
+    assert_eq!(normalize(1495., &cal, 5.), 0.);
+    // just outside response starts from zero, without a step
+    assert!(close(normalize(1506., &cal, 5.), 1. / 495.));
+    assert!(close(normalize(1494., &cal, 5.), -1. / 495.));
+    assert_eq!(normalize(2000., &cal, 5.), 1.);
+    assert_eq!(normalize(1000., &cal, 5.), -1.);
+}
+
+#[test]
+fn asymmetric_calibration() {
+    let cal = Calibration {
+        min: 1100.,
+        mid: 1400.,
+        max: 1900.,
+    };
+    // each half of stick is scaled on its own
+    assert_eq!(normalize(1400., &cal, 0.), 0.);
+    assert!(close(normalize(1250., &cal, 0.), -0.5));
+    assert!(close(normalize(1650., &cal, 0.), 0.5));
+    assert_eq!(normalize(1100., &cal, 0.), -1.);
+    assert_eq!(normalize(1900., &cal, 0.), 1.);
+    assert!(close(throttle(1500., &cal), 0.5));
+}
+
+#[test]
+fn clamped_to_endpoints() {
+    let cal = Calibration::new();
+    assert_eq!(normalize(2200., &cal, 5.), 1.);
+    assert_eq!(normalize(800., &cal, 5.), -1.);
+    assert_eq!(throttle(2200., &cal), 1.);
+    assert_eq!(throttle(800., &cal), 0.);
+    // degenerate calibration gives no output
+    let flat = Calibration {
+        min: 1500.,
+        mid: 1500.,
+        max: 1500.,
+    };
+    assert_eq!(normalize(1800., &flat, 5.), 0.);
+    assert_eq!(throttle(1800., &flat), 0.);
+}
+
+#[test]
+fn expo_endpoints() {
+    for stick in [-1., -0.5, 0., 0.3, 1.].iter() {
+        assert_eq!(expo(*stick, 0.), *stick);
+        assert!(close(expo(*stick, 1.), stick * stick * stick));
+    }
+    // full stick is full output at any expo
+    assert_eq!(expo(1., 0.7), 1.);
+    assert_eq!(expo(-1., 0.7), -1.);
+}
+
+#[test]
+fn taer_mapping() {
+    let mut config = Config::new();
+    config.map = ChannelMap::Taer;
+    config.deadband = 0.;
+    let mut input = Input::new();
+    input.count = 4;
+    // thrust, roll, pitch, yaw
+    input.channels[..4].copy_from_slice(&[2000, 2000, 1000, 1750]);
+    let setpoints = process(&input, &config);
+    assert_eq!(setpoints.rates, [200., -200., 100.]);
+    assert_eq!(setpoints.degrees, [30., -30.]);
+    assert_eq!(setpoints.thrust, 2000.);
+    config.map = ChannelMap::Aetr;
+    let setpoints = process(&input, &config);
+    assert_eq!(setpoints.rates, [200., 200., 100.]);
+    assert_eq!(setpoints.thrust, 0.);
+}
+
+#[test]
+fn virtual_sticks_expire() {
+    let mut control = Control::new();
+    let mut state = State::new();
+    state.ahrs.dt_s = 0.004;
+    state.loops = 100;
+    let mut cmd = cmd::create();
+    for b in b"rc=1800,1500,1500,1700\n".iter() {
+        cmd.feed(*b, &mut control, &state);
+    }
+    assert_eq!(control.rc_input.virtual_at, Some(100));
+    assert!(control.thrust > 0. && control.target_rates.roll > 0.);
+    // other commands keep link alive but do not refresh sticks
+    let timeout = (control.link_timeout / state.ahrs.dt_s) as u32;
+    state.loops += timeout;
+    control.link_seq = control.link_seq.wrapping_add(1);
+    expire(&state, &mut control);
+    assert!(control.thrust > 0.);
+    state.loops += 2;
+    expire(&state, &mut control);
+    assert_eq!(control.thrust, 0.);
+    assert_eq!(control.target_rates.roll, 0.);
+    assert_eq!(control.rc_input.virtual_at, None);
+}
@@ -0,0 +1,107 @@
+use fcfs_host_tests::rc::Input;
+use fcfs_host_tests::sbus::{self, Sbus};
+
+const HEADER: u8 = 0x0F;
+const FRAME_LOST: u8 = 1 << 2;
+const FAILSAFE: u8 = 1 << 3;
+
+// header, 16 channels of 11 bits LSB first, flags and footer
+fn frame(channels: &[u16; 16], flags: u8, footer: u8) -> [u8; 25] {
+    let mut frame = [0; 25];
+    frame[0] = HEADER;
+    let mut bits = 0u32;
+    let mut count = 0;
+    let mut pos = 1;
+    for channel in channels.iter() {
+        bits |= (*channel as u32 & 0x07FF) << count;
+        count += 11;
+        while count >= 8 {
+            frame[pos] = bits as u8;
+            pos += 1;
+            bits >>= 8;
+            count -= 8;
+        }
+    }
+    frame[23] = flags;
+    frame[24] = footer;
+    frame
+}
+
+fn sticks() -> [u16; 16] {
+    let mut channels = [992; 16];
+    for (i, channel) in channels.iter_mut().enumerate() {
+        *channel = 300 + i as u16 * 100;
+    }
+    channels
+}
+
+// All inputs returned while feeding all bytes
+fn feed(sbus: &mut Sbus, bytes: &[u8]) -> Vec<Input> {
+    bytes.iter().filter_map(|b| sbus.feed(*b)).collect()
+}
+
+#[test]
+fn sixteen_channels_unpacked() {
+    let mut channels = sticks();
+    channels[0] = 172;
+    channels[15] = 1811;
+    let input = sbus::decode(&frame(&channels, 0, 0));
+    assert_eq!(input.count, 16);
+    assert_eq!(input.channels[0], 987);
+    assert_eq!(input.channels[15], 2011);
+    for (i, channel) in channels.iter().enumerate().take(15).skip(1) {
+        let expected = channel * 5 / 8 + 880;
+        assert_eq!(input.channels[i], expected, "channel {}", i);
+    }
+    assert!(!input.failsafe && !input.frame_lost);
+}
+
+#[test]
+fn flags_reported() {
+    let input = sbus::decode(&frame(&sticks(), FRAME_LOST, 0));
+    assert!(input.frame_lost && !input.failsafe);
+    let input = sbus::decode(&frame(&sticks(), FAILSAFE | FRAME_LOST, 0));
+    assert!(input.frame_lost && input.failsafe);
+    // digital channels 17 and 18 do not count as either
+    let input = sbus::decode(&frame(&sticks(), 0b11, 0));
+    assert!(!input.frame_lost && !input.failsafe);
+}
+
+#[test]
+fn header_and_footer_checked() {
+    let mut sbus = sbus::create();
+    let good = frame(&sticks(), 0, 0);
+    // SBUS2 telemetry slot footers are accepted
+    for footer in [0x04, 0x14, 0x24, 0x34].iter() {
+        let inputs = feed(&mut sbus, &frame(&sticks(), 0, *footer));
+        assert_eq!(inputs.len(), 1, "footer {:02x}", footer);
+    }
+    let mut bad_footer = good;
+    bad_footer[24] = 0x55;
+    assert!(feed(&mut sbus, &bad_footer).is_empty());
+    // bytes before header are skipped
+    let mut noisy = vec![0x00, 0xAA];
+    noisy.extend_from_slice(&good);
+    assert_eq!(feed(&mut sbus, &noisy).len(), 1);
+}
+
+#[test]
+fn resync_after_lost_byte() {
+    let mut sbus = sbus::create();
+    let good = frame(&sticks(), 0, 0);
+    let mut stream = Vec::new();
+    // byte dropped in the middle of first frame
+    stream.extend_from_slice(&good[..10]);
+    stream.extend_from_slice(&good[11..]);
+    for _ in 0..4 {
+        stream.extend_from_slice(&good);
+    }
+    let inputs = feed(&mut sbus, &stream);
+    // short frame and the one it runs into are dropped, not decoded
+    // as garbage; decoder is back in step right after them
+    assert_eq!(inputs.len(), 3);
+    let expected = sbus::decode(&good);
+    for input in inputs.iter() {
+        assert_eq!(input.channels, expected.channels);
+    }
+}
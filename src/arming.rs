@@ -0,0 +1,57 @@
+use crate::battery;
+use crate::types::{self, ArmRefusal};
+use crate::utils::to_rads;
+
+// accelerometer norm has to be within this fraction of G
+const ACCEL_TOLERANCE: f32 = 0.3;
+
+// Evaluates arming request against current state.
+// Returns false if request was refused and has to be dropped.
+pub fn update(state: &mut types::State, control: &types::Control) -> bool {
+    if !control.arm {
+        state.armed = false;
+        return true;
+    }
+    if state.armed {
+        // checks are for the ground only
+        return true;
+    }
+    state.arm_refusal = check(state, control);
+    state.armed = state.arm_refusal.is_none();
+    state.armed
+}
+
+pub fn check(
+    state: &types::State,
+    control: &types::Control,
+) -> Option<ArmRefusal> {
+    if state.motor_test.is_some() {
+        return Some(ArmRefusal::MotorTest);
+    }
+    if control.thrust > control.arm_thrust {
+        return Some(ArmRefusal::Thrust);
+    }
+    if !imu_healthy(state) {
+        return Some(ArmRefusal::Imu);
+    }
+    if !state.mixer_ok {
+        return Some(ArmRefusal::Mixer);
+    }
+    if state.battery.status != battery::Status::Ok {
+        return Some(ArmRefusal::Battery);
+    }
+    let max_tilt = to_rads(control.arm_max_tilt);
+    let ypr = state.ahrs.ypr;
+    if libm::fabsf(ypr.roll) > max_tilt || libm::fabsf(ypr.pitch) > max_tilt {
+        return Some(ArmRefusal::Attitude);
+    }
+    None
+}
+
+fn imu_healthy(state: &types::State) -> bool {
+    let a = state.ahrs.accel;
+    let norm = libm::sqrtf(a[0] * a[0] + a[1] * a[1] + a[2] * a[2]);
+    state.imu_errors == 0
+        && state.ahrs.dt_s > 0.
+        && libm::fabsf(norm - mpu9250::G) < mpu9250::G * ACCEL_TOLERANCE
+}
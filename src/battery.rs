@@ -0,0 +1,314 @@
+use crate::filters::Pt1;
+use crate::types;
+use crate::utils::clamp;
+
+pub const ADC_MAX: f32 = 4095.0;
+pub const ADC_VREF: f32 = 3.3;
+// readings are noisy and sag with load
+const FILTER_HZ: f32 = 2.0;
+// seconds below threshold before status is raised
+const HOLD_S: f32 = 2.0;
+// below this there is no battery, board is powered from USB
+const MIN_VOLTAGE: f32 = 2.0;
+// fully charged cell, used for cell count detection
+const MAX_CELL_V: f32 = 4.3;
+// voltage compensation never scales output beyond these
+const COMPENSATION_LIMITS: (f32, f32) = (0.8, 1.3);
+
+// Latest ADC conversions, None if channel is not sampled
+#[derive(Copy, Clone)]
+pub struct Raw {
+    pub voltage: Option<u16>,
+    pub current: Option<u16>,
+}
+
+impl Raw {
+    #[inline]
+    pub const fn new() -> Self {
+        Raw {
+            voltage: None,
+            current: None,
+        }
+    }
+}
+
+pub trait Sensor {
+    /// Returns latest conversions and starts next ones
+    fn read(&mut self) -> Raw;
+}
+
+impl Sensor for () {
+    // dummy
+    fn read(&mut self) -> Raw {
+        Raw::new()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, PartialOrd)]
+pub enum Status {
+    Ok,
+    Warning,
+    Critical,
+}
+
+impl Status {
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Critical => "critical",
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Config {
+    // battery volts per ADC volt, i.e. divider ratio
+    pub voltage_scale: f32,
+    // amperes per ADC volt and ADC volts at zero current
+    pub current_scale: f32,
+    pub current_offset: f32,
+    // 0 detects cell count while disarmed
+    pub cells: u8,
+    // cell volts
+    pub warning_v: f32,
+    pub critical_v: f32,
+    // scale output by nominal/measured voltage, nominal is per cell
+    pub compensation: bool,
+    pub nominal_v: f32,
+}
+
+impl Config {
+    #[inline]
+    pub const fn new() -> Self {
+        Config {
+            voltage_scale: 11.0,
+            current_scale: 40.0,
+            current_offset: 0.0,
+            cells: 0,
+            warning_v: 3.5,
+            critical_v: 3.3,
+            compensation: false,
+            nominal_v: 3.8,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Battery {
+    pub voltage: f32,
+    pub current: f32,
+    // consumed since power on
+    pub mah: f32,
+    // 0 if there is no battery
+    pub cells: u8,
+    pub status: Status,
+    // time spent below threshold of next status
+    low_time_s: f32,
+    voltage_filter: Pt1,
+    current_filter: Pt1,
+}
+
+impl Battery {
+    #[inline]
+    pub const fn new() -> Self {
+        Battery {
+            voltage: 0.0,
+            current: 0.0,
+            mah: 0.0,
+            cells: 0,
+            status: Status::Ok,
+            low_time_s: 0.0,
+            voltage_filter: Pt1::new(),
+            current_filter: Pt1::new(),
+        }
+    }
+
+    #[inline]
+    pub fn cell_voltage(&self) -> Option<f32> {
+        if self.cells > 0 {
+            Some(self.voltage / self.cells as f32)
+        } else {
+            None
+        }
+    }
+}
+
+#[inline]
+fn adc_volts(raw: u16) -> f32 {
+    raw as f32 * ADC_VREF / ADC_MAX
+}
+
+// Updates readings and status. Status only rises in flight,
+// so that voltage recovering with lower load does not cancel failsafe.
+pub fn update(state: &mut types::State, control: &types::Control, raw: Raw) {
+    let config = &control.battery;
+    let dt_s = state.ahrs.dt_s;
+    let battery = &mut state.battery;
+    if let Some(v) = raw.voltage {
+        let voltage = adc_volts(v) * config.voltage_scale;
+        battery.voltage =
+            battery.voltage_filter.apply(voltage, FILTER_HZ, dt_s);
+    }
+    if let Some(c) = raw.current {
+        let current =
+            (adc_volts(c) - config.current_offset) * config.current_scale;
+        battery.current =
+            battery.current_filter.apply(current, FILTER_HZ, dt_s);
+        // 1 mAh is 3.6 As
+        battery.mah += battery.current * dt_s / 3.6;
+    }
+
+    if !state.armed {
+        battery.cells = if battery.voltage < MIN_VOLTAGE {
+            0
+        } else if config.cells > 0 {
+            config.cells
+        } else {
+            libm::ceilf(battery.voltage / MAX_CELL_V) as u8
+        };
+    }
+    let level = match battery.cell_voltage() {
+        Some(v) if v < config.critical_v => Status::Critical,
+        Some(v) if v < config.warning_v => Status::Warning,
+        _ => Status::Ok,
+    };
+    if level > battery.status {
+        battery.low_time_s += dt_s;
+        if battery.low_time_s > HOLD_S {
+            battery.status = level;
+            battery.low_time_s = 0.;
+        }
+    } else {
+        battery.low_time_s = 0.;
+        if !state.armed {
+            battery.status = level;
+        }
+    }
+}
+
+// Output scale that keeps thrust independent of pack sag;
+// 1 if compensation is off or there is no sane measurement.
+pub fn compensation(battery: &Battery, config: &Config) -> f32 {
+    // sensor disconnected in flight reads as no battery
+    if !config.compensation || battery.voltage < MIN_VOLTAGE {
+        return 1.;
+    }
+    let (min, max) = COMPENSATION_LIMITS;
+    battery
+        .cell_voltage()
+        .map_or(1., |v| clamp(config.nominal_v / v, min, max))
+}
+
+#[cfg(target_os = "none")]
+pub mod stm32f30x {
+    use hal::pac::{ADC1_2, ADC2, RCC};
+
+    use super::{Raw, Sensor};
+
+    const RCC_AHBENR_ADC12EN: u32 = 1 << 28;
+    // synchronous clock, HCLK / 1
+    const CCR_CKMODE: u32 = 0b01 << 16;
+    const CR_ADVREGEN_MASK: u32 = 0b11 << 28;
+    const CR_ADVREGEN: u32 = 0b01 << 28;
+    const CR_ADCAL: u32 = 1 << 31;
+    const CR_ADSTART: u32 = 1 << 2;
+    const CR_ADEN: u32 = 1;
+    const ISR_ADRDY: u32 = 1;
+    const ISR_EOC: u32 = 1 << 2;
+    // longest sample time (601.5 cycles) for high impedance dividers
+    const SMPR_LONGEST: u32 = 0b111;
+
+    // ADC2 channels: IN1 is battery divider, IN2 is current sensor
+    const VOLTAGE_CHANNEL: u32 = 1;
+    const CURRENT_CHANNEL: u32 = 2;
+
+    // Converts one channel at a time, never waits for conversion
+    pub struct Adc<P> {
+        // pins have to be in analog mode; kept to own them
+        pins: P,
+        has_current: bool,
+        channel: u32,
+        raw: Raw,
+    }
+
+    impl<P> Adc<P> {
+        pub fn new(pins: P, has_current: bool) -> Self {
+            let rcc = unsafe { &*RCC::ptr() };
+            let common = unsafe { &*ADC1_2::ptr() };
+            let adc = unsafe { &*ADC2::ptr() };
+            (*rcc).ahbenr.modify(|r, w| unsafe {
+                w.bits(r.bits() | RCC_AHBENR_ADC12EN)
+            });
+            (*common)
+                .ccr
+                .modify(|r, w| unsafe { w.bits(r.bits() | CCR_CKMODE) });
+            // voltage regulator goes through reset state and needs 10us
+            (*adc)
+                .cr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !CR_ADVREGEN_MASK) });
+            (*adc)
+                .cr
+                .modify(|r, w| unsafe { w.bits(r.bits() | CR_ADVREGEN) });
+            cortex_m::asm::delay(1_000);
+            (*adc)
+                .cr
+                .modify(|r, w| unsafe { w.bits(r.bits() | CR_ADCAL) });
+            while (*adc).cr.read().bits() & CR_ADCAL != 0 {
+                cortex_m::asm::nop();
+            }
+            (*adc)
+                .cr
+                .modify(|r, w| unsafe { w.bits(r.bits() | CR_ADEN) });
+            while (*adc).isr.read().bits() & ISR_ADRDY == 0 {
+                cortex_m::asm::nop();
+            }
+            let smpr = (SMPR_LONGEST << (VOLTAGE_CHANNEL * 3))
+                | (SMPR_LONGEST << (CURRENT_CHANNEL * 3));
+            (*adc).smpr1.write(|w| unsafe { w.bits(smpr) });
+            let mut result = Adc {
+                pins,
+                has_current,
+                channel: VOLTAGE_CHANNEL,
+                raw: Raw::new(),
+            };
+            result.start();
+            result
+        }
+
+        fn start(&mut self) {
+            let adc = unsafe { &*ADC2::ptr() };
+            // sequence of one conversion
+            (*adc).sqr1.write(|w| unsafe { w.bits(self.channel << 6) });
+            (*adc)
+                .cr
+                .modify(|r, w| unsafe { w.bits(r.bits() | CR_ADSTART) });
+        }
+    }
+
+    impl<P> Sensor for Adc<P> {
+        fn read(&mut self) -> Raw {
+            let adc = unsafe { &*ADC2::ptr() };
+            if (*adc).isr.read().bits() & ISR_EOC == 0 {
+                return self.raw;
+            }
+            // reading data clears EOC
+            let value = (*adc).dr.read().bits() as u16;
+            if self.channel == VOLTAGE_CHANNEL {
+                self.raw.voltage = Some(value);
+            } else {
+                self.raw.current = Some(value);
+            }
+            if self.has_current {
+                self.channel = if self.channel == VOLTAGE_CHANNEL {
+                    CURRENT_CHANNEL
+                } else {
+                    VOLTAGE_CHANNEL
+                };
+            }
+            self.start();
+            self.raw
+        }
+    }
+}
@@ -7,11 +7,15 @@ pub struct BoardConfiguration<
     NPT,
     Usart,
     UsartPins,
+    RcUsart,
+    RcUsartPins,
     TxCh,
     GP,
     ExtiNum,
     MotorPins,
     MotorAux,
+    BatteryPins,
+    PpmPin,
 > where
     ExtiNum: hal::exti::ExternalInterrupt,
     GP: hal::gpio::GPIOPin,
@@ -22,10 +26,16 @@ pub struct BoardConfiguration<
     pub ncs: NPT,
     pub usart: Usart,
     pub usart_pins: UsartPins,
+    // receiver
+    pub rc_usart: RcUsart,
+    pub rc_usart_pins: RcUsartPins,
     pub tx_ch: TxCh,
     pub extih: hal::exti::BoundInterrupt<GP, ExtiNum>,
     pub motor_pins: MotorPins,
     pub motor_aux: MotorAux,
+    pub battery_pins: BatteryPins,
+    // PPM receiver input, TIM4 CH1
+    pub ppm_pin: PpmPin,
 }
 
 pub struct Peripherals {
@@ -45,6 +55,7 @@ pub struct Peripherals {
 }
 
 pub type Motors = impl crate::mixer::MotorCtrl;
+pub type Battery = impl crate::battery::Sensor;
 
 macro_rules! pwm {
     ($pin: expr,
@@ -74,6 +85,12 @@ mod defs {
         (gpio::PA14<PullNone, Input>, gpio::PA15<PullNone, Input>);
     pub type TxUsart = Tx<USART>;
     pub type RxUsart = Rx<USART>;
+    pub type RcUsart = hal::pac::USART1;
+    pub type RcUsartPins =
+        (gpio::PA9<PullNone, Input>, gpio::PA10<PullNone, Input>);
+    pub type RcRx = Rx<RcUsart>;
+    pub type RcTx = Tx<RcUsart>;
+    pub type PpmPin = gpio::PB6<PullNone, Input>;
     pub type TxCh = hal::dma::dma1::C7;
     pub type ExtiNum = hal::exti::EXTI13;
     pub type MotorPins = (
@@ -84,7 +101,12 @@ mod defs {
         gpio::PA6<PullNone, gpio::Input>,
         gpio::PA7<PullNone, gpio::Input>,
     );
-    pub type MotorAux = (hal::pac::TIM2, hal::pac::TIM3);
+    pub type MotorAux = (hal::pac::TIM2, hal::pac::TIM3, dma1::C2, dma1::C3);
+    // ADC2: battery divider, current sensor
+    pub type BatteryPins = (
+        gpio::PA4<PullNone, gpio::Input>,
+        gpio::PA5<PullNone, gpio::Input>,
+    );
 
     type Res = BoardConfiguration<
         DT,
@@ -93,11 +115,15 @@ mod defs {
         NT,
         USART,
         UsartPins,
+        RcUsart,
+        RcUsartPins,
         TxCh,
         MpuIntPin,
         ExtiNum,
         MotorPins,
         MotorAux,
+        BatteryPins,
+        PpmPin,
     >;
     pub fn configure(mut device: Peripherals) -> Res {
         let scl_sck = device.gpiob.pb3;
@@ -118,7 +144,12 @@ mod defs {
             device.gpioa.pa6,
             device.gpioa.pa7,
         );
-        let motor_aux = (device.tim2, device.tim3);
+        let motor_aux = (
+            device.tim2,
+            device.tim3,
+            device.dma_channels.2,
+            device.dma_channels.3,
+        );
 
         BoardConfiguration {
             debug_pin: device.gpioc.pc15,
@@ -127,13 +158,18 @@ mod defs {
             ncs: device.gpiob.pb9,
             usart: device.usart2,
             usart_pins: (device.gpioa.pa14, device.gpioa.pa15),
+            rc_usart: device.usart1,
+            rc_usart_pins: (device.gpioa.pa9, device.gpioa.pa10),
             tx_ch: device.dma_channels.7,
             extih,
             motor_pins,
             motor_aux,
+            battery_pins: (device.gpioa.pa4, device.gpioa.pa5),
+            ppm_pin: device.gpiob.pb6,
         }
     }
 
+    #[cfg(esc = "esc_pwm")]
     pub fn setup_motors(
         motor_pins: MotorPins,
         motor_aux: MotorAux,
@@ -150,51 +186,101 @@ mod defs {
         let mut m4_front_left = pwm!(motor_pins.3, ch4);
         timer2.enable();
 
-        let max_duty = m1_rear_right.get_max_duty() as f32;
-
-        #[cfg(motors = "motors_quad")]
-        {
-            #[rustfmt::skip]
-            let map = [
-                [ 1., -1., -1., 1.], /* rear right */
-                [1.,  1.,  1., 1.], /* front right */
-                [ -1., -1., -1., 1.], /* rear left */
-                [-1.,  1.,  1., 1.] /* front left */
-            ];
-            let pin =
-                (m1_rear_right, m2_front_right, m3_rear_left, m4_front_left);
-            crate::mixer::Mixer { map, pin, max_duty }
-        }
+        let ((ch5, ch6, _, _), mut timer3) =
+            hal::timer::tim3::Timer::new(motor_aux.1, freq, clocks).use_pwm();
+        let mut m5 = pwm!(motor_pins.4, ch5);
+        let mut m6 = pwm!(motor_pins.5, ch6);
+        timer3.enable();
+
+        // geometry (and motor order) is configured at runtime
+        let pin = (
+            m1_rear_right,
+            m2_front_right,
+            m3_rear_left,
+            m4_front_left,
+            m5,
+            m6,
+        );
+        crate::mixer::Mixer::new(pin)
+    }
 
-        #[cfg(motors = "motors_hex")]
-        {
-            let ((ch5, ch6, _, _), mut timer3) =
-                hal::timer::tim3::Timer::new(motor_aux.1, freq, clocks)
-                    .use_pwm();
-            let mut m5_left = pwm!(motor_pins.4, ch5);
-            let mut m6_right = pwm!(motor_pins.5, ch6);
-            timer3.enable();
-
-            #[rustfmt::skip]
-            let map = [
-                [0.567, -0.815, -1.0, 1.0], /* rear right */
-                [0.567, 0.815, -1.0, 1.0], /* front right */
-                [-0.567, -0.815, 1.0, 1.0], /* rear left */
-                [-0.567, 0.815, 1.0, 1.0], /* front left */
-                [-1.0, -0.0, -1.0, 1.0], /* left */
-                [1.0, -0.0, 1.0, 1.0] /* right */
-            ];
-
-            let pin = (
-                m1_rear_right,
-                m2_front_right,
-                m3_rear_left,
-                m4_front_left,
-                m5_left,
-                m6_right,
-            );
-            crate::mixer::Mixer { map, pin, max_duty }
-        }
+    #[cfg(any(
+        esc = "esc_dshot300",
+        esc = "esc_dshot600",
+        esc = "esc_bdshot300",
+        esc = "esc_bdshot600"
+    ))]
+    pub fn setup_motors(
+        motor_pins: MotorPins,
+        motor_aux: MotorAux,
+        clocks: hal::rcc::Clocks,
+        freq: Hertz<u32>,
+    ) -> Motors {
+        // timers run at DShot bitrate, DMA updates duty every bit
+        let bitrate = Hertz(crate::dshot::BITRATE);
+        let ((ch1, ch2, ch3, ch4), mut timer2) =
+            hal::timer::tim2::Timer::new(motor_aux.0, bitrate, clocks)
+                .use_pwm();
+        let ((ch5, ch6, _, _), mut timer3) =
+            hal::timer::tim3::Timer::new(motor_aux.1, bitrate, clocks)
+                .use_pwm();
+        let mut m1 = pwm!(motor_pins.0, ch1);
+        let mut m2 = pwm!(motor_pins.1, ch2);
+        let mut m3 = pwm!(motor_pins.2, ch3);
+        let mut m4 = pwm!(motor_pins.3, ch4);
+        let mut m5 = pwm!(motor_pins.4, ch5);
+        let mut m6 = pwm!(motor_pins.5, ch6);
+        let period = m1.get_max_duty();
+        let pins = (m1, m2, m3, m4, m5, m6);
+        let out = crate::dshot::stm32f30x::DShot::new(
+            pins,
+            period,
+            motor_aux.2,
+            motor_aux.3,
+        );
+        timer2.enable();
+        timer3.enable();
+        crate::mixer::Mixer::new(out)
+    }
+
+    #[cfg(any(
+        esc = "esc_oneshot125",
+        esc = "esc_oneshot42",
+        esc = "esc_multishot"
+    ))]
+    pub fn setup_motors(
+        motor_pins: MotorPins,
+        motor_aux: MotorAux,
+        clocks: hal::rcc::Clocks,
+        freq: Hertz<u32>,
+    ) -> Motors {
+        // timer period fits the longest pulse, pulses are sent by mixer
+        let pulse_us = crate::oneshot::PULSE_US;
+        let pulse = Hertz(crate::oneshot::timer_hz(pulse_us));
+        let ((ch1, ch2, ch3, ch4), mut timer2) =
+            hal::timer::tim2::Timer::new(motor_aux.0, pulse, clocks).use_pwm();
+        let ((ch5, ch6, _, _), mut timer3) =
+            hal::timer::tim3::Timer::new(motor_aux.1, pulse, clocks).use_pwm();
+        let mut m1 = pwm!(motor_pins.0, ch1);
+        let mut m2 = pwm!(motor_pins.1, ch2);
+        let mut m3 = pwm!(motor_pins.2, ch3);
+        let mut m4 = pwm!(motor_pins.3, ch4);
+        let mut m5 = pwm!(motor_pins.4, ch5);
+        let mut m6 = pwm!(motor_pins.5, ch6);
+        let pins = (m1, m2, m3, m4, m5, m6);
+        let out = crate::oneshot::stm32f30x::OneShot::new(pins, pulse_us);
+        timer2.enable();
+        timer3.enable();
+        crate::mixer::Mixer::new(out)
+    }
+
+    pub fn setup_battery(battery_pins: BatteryPins) -> Battery {
+        // switch pa4, pa5 to analog mode
+        let gpioa = unsafe { &*hal::pac::GPIOA::ptr() };
+        (*gpioa).moder.modify(|r, w| unsafe {
+            w.bits(r.bits() | (0b11 << 8) | (0b11 << 10))
+        });
+        crate::battery::stm32f30x::Adc::new(battery_pins, true)
     }
 }
 
@@ -215,10 +301,17 @@ mod defs {
         (gpio::PA2<PullNone, Input>, gpio::PA15<PullNone, Input>);
     pub type TxUsart = Tx<USART>;
     pub type RxUsart = Rx<USART>;
+    pub type RcUsart = hal::pac::USART1;
+    pub type RcUsartPins =
+        (gpio::PA9<PullNone, Input>, gpio::PA10<PullNone, Input>);
+    pub type RcRx = Rx<RcUsart>;
+    pub type RcTx = Tx<RcUsart>;
+    pub type PpmPin = gpio::PB6<PullNone, Input>;
     pub type TxCh = hal::dma::dma1::C7;
     pub type ExtiNum = hal::exti::EXTI0;
     pub type MotorPins = ();
     pub type MotorAux = ();
+    pub type BatteryPins = ();
 
     type Res = BoardConfiguration<
         DT,
@@ -227,11 +320,15 @@ mod defs {
         NT,
         USART,
         UsartPins,
+        RcUsart,
+        RcUsartPins,
         TxCh,
         MpuIntPin,
         ExtiNum,
         MotorPins,
         MotorAux,
+        BatteryPins,
+        PpmPin,
     >;
     pub fn configure(mut device: Peripherals) -> Res {
         let scl_sck = device.gpiob.pb3;
@@ -251,10 +348,14 @@ mod defs {
             ncs: device.gpiob.pb0,
             usart: device.usart2,
             usart_pins: (device.gpioa.pa2, device.gpioa.pa15),
+            rc_usart: device.usart1,
+            rc_usart_pins: (device.gpioa.pa9, device.gpioa.pa10),
             tx_ch: device.dma_channels.7,
             extih,
             motor_pins: (),
             motor_aux: (),
+            battery_pins: (),
+            ppm_pin: device.gpiob.pb6,
         }
     }
 
@@ -266,6 +367,10 @@ mod defs {
     ) -> Motors {
         // no motors in Dev
     }
+
+    pub fn setup_battery(battery_pins: BatteryPins) -> Battery {
+        // no battery in Dev
+    }
 }
 
 pub use defs::*;
@@ -337,7 +442,10 @@ pub mod mydevice {
         EXTI15_10 = hal::pac::Interrupt::EXTI15_10 as u8,
         EXTI0 = hal::pac::Interrupt::EXTI0 as u8,
 
+        USART1_EXTI25 = hal::pac::Interrupt::USART1_EXTI25 as u8,
         USART2_EXTI26 = hal::pac::Interrupt::USART2_EXTI26 as u8,
+        TIM4 = hal::pac::Interrupt::TIM4 as u8,
+        DMA1_CH2 = hal::pac::Interrupt::DMA1_CH2 as u8,
     }
     pub use Interrupt as interrupt;
 
@@ -1,11 +1,444 @@
-use crate::types;
+use crate::dshot::Command;
+use crate::filters;
+use crate::mixer::{self, Preset};
+use crate::modes;
+use crate::motortest;
+use crate::rc;
+use crate::types::{self, MotorTest};
+use crate::utils::clamp;
 
-fn parse<T, E>(bytes: &[u8]) -> Result<T, E>
+// Value bytes come from serial line as is, so may be any garbage
+fn parse<T>(bytes: &[u8]) -> Result<T, ()>
 where
-    T: core::str::FromStr<Err = E>,
+    T: core::str::FromStr,
 {
-    let v = unsafe { core::str::from_utf8_unchecked(bytes) };
-    T::from_str(v)
+    let v = core::str::from_utf8(bytes).map_err(|_| ())?;
+    T::from_str(v).map_err(|_| ())
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Error {
+    // no command with such name
+    Unknown,
+    // command is known, its value is not
+    Malformed,
+    // command is refused while motors are armed
+    Armed,
+    // motor test is refused until props are confirmed removed
+    Props,
+}
+
+impl Error {
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Error::Unknown => "unknown",
+            Error::Malformed => "malformed",
+            Error::Armed => "armed",
+            Error::Props => "props",
+        }
+    }
+}
+
+// Longer commands are cut in replies
+pub type Echo = heapless::Vec<u8, heapless::consts::U128>;
+
+// Values as applied, in command units
+type Applied = heapless::Vec<f32, heapless::consts::U16>;
+
+// Outcome of every complete command line
+#[derive(Clone)]
+pub struct Reply {
+    pub result: Result<(), Error>,
+    pub request: Option<types::Requests>,
+    // command name and applied values, or command as received
+    pub echo: Echo,
+}
+
+// Replies waiting for command channel
+pub type Replies = heapless::spsc::Queue<Reply, heapless::consts::U4>;
+
+// Word as is or in double quotes
+fn unquote(s: &str) -> Result<&str, ()> {
+    let quoted = s.starts_with('"');
+    if quoted != (s.len() > 1 && s.ends_with('"')) {
+        return Err(());
+    }
+    let word = if quoted { &s[1..s.len() - 1] } else { s };
+    if word.contains('"') {
+        return Err(());
+    }
+    Ok(word)
+}
+
+impl core::str::FromStr for types::FlightMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let modes = [
+            types::FlightMode::Acro,
+            types::FlightMode::Angle,
+            types::FlightMode::Horizon,
+        ];
+        let word = unquote(s)?;
+        modes.iter().find(|m| m.as_str() == word).copied().ok_or(())
+    }
+}
+
+impl core::str::FromStr for rc::ChannelMap {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let maps = [rc::ChannelMap::Aetr, rc::ChannelMap::Taer];
+        let word = unquote(s)?;
+        maps.iter().find(|m| m.as_str() == word).copied().ok_or(())
+    }
+}
+
+impl core::str::FromStr for Preset {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match unquote(s)? {
+            "quadx" => Ok(Preset::QuadX),
+            "quadp" => Ok(Preset::QuadPlus),
+            "hexx" => Ok(Preset::HexX),
+            "hexp" => Ok(Preset::HexPlus),
+            "y6" => Ok(Preset::Y6),
+            "octox" => Ok(Preset::OctoX),
+            _ => Err(()),
+        }
+    }
+}
+
+impl core::str::FromStr for filters::Kind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match unquote(s)? {
+            "none" => Ok(filters::Kind::None),
+            "pt1" => Ok(filters::Kind::Pt1),
+            "biquad" => Ok(filters::Kind::Biquad),
+            _ => Err(()),
+        }
+    }
+}
+
+// on or off
+struct Switch(bool);
+
+impl core::str::FromStr for Switch {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match unquote(s)? {
+            "on" => Ok(Switch(true)),
+            "off" => Ok(Switch(false)),
+            _ => Err(()),
+        }
+    }
+}
+
+// normal or reversed, as ESC command
+struct SpinDirection(Command);
+
+impl core::str::FromStr for SpinDirection {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match unquote(s)? {
+            "normal" => Ok(SpinDirection(Command::SpinDirectionNormal)),
+            "reversed" => Ok(SpinDirection(Command::SpinDirectionReversed)),
+            _ => Err(()),
+        }
+    }
+}
+
+// Float other than nan or infinity, including overflow
+fn finite(s: &str) -> Result<f32, ()> {
+    let value: f32 = s.parse().map_err(|_| ())?;
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(())
+    }
+}
+
+// any finite value
+struct Finite(f32);
+
+impl core::str::FromStr for Finite {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        finite(s).map(Finite)
+    }
+}
+
+// thrust, times, limits and other quantities below zero make no sense
+struct NonNegative(f32);
+
+impl core::str::FromStr for NonNegative {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match finite(s)? {
+            v if v >= 0. => Ok(NonNegative(v)),
+            _ => Err(()),
+        }
+    }
+}
+
+// fraction from 0 to 100
+struct Percent(f32);
+
+impl core::str::FromStr for Percent {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match finite(s)? {
+            v if (0. ..=100.).contains(&v) => Ok(Percent(v)),
+            _ => Err(()),
+        }
+    }
+}
+
+// Parses exactly `out.len()` comma separated finite floats
+fn parse_floats<'a, I>(mut parts: I, out: &mut [f32]) -> Result<(), ()>
+where
+    I: Iterator<Item = &'a str>,
+{
+    for v in out.iter_mut() {
+        *v = finite(parts.next().ok_or(())?)?;
+    }
+    match parts.next() {
+        Some(_) => Err(()),
+        None => Ok(()),
+    }
+}
+
+// index,output (percent),time (ms)
+struct MotorSpin {
+    motor: usize,
+    output: f32,
+    time_ms: f32,
+}
+
+impl core::str::FromStr for MotorSpin {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let motor = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        if motor >= mixer::MAX_MOTORS {
+            return Err(());
+        }
+        let mut values = [0.; 2];
+        parse_floats(parts, &mut values)?;
+        let [output, time_ms] = values;
+        if !(0. ..=100.).contains(&output) || time_ms < 0. {
+            return Err(());
+        }
+        Ok(MotorSpin {
+            motor,
+            output,
+            time_ms,
+        })
+    }
+}
+
+// number of mixer rows in use, at least one
+struct MotorCount(usize);
+
+impl core::str::FromStr for MotorCount {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let motors = s.parse().map_err(|_| ())?;
+        if motors == 0 || motors > mixer::MAX_MOTORS {
+            return Err(());
+        }
+        Ok(MotorCount(motors))
+    }
+}
+
+// index,roll,pitch,yaw,thrust
+struct MotorRow {
+    index: usize,
+    row: mixer::Row,
+}
+
+impl core::str::FromStr for MotorRow {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let index = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        if index >= mixer::MAX_MOTORS {
+            return Err(());
+        }
+        let mut row = [0.; 4];
+        parse_floats(parts, &mut row)?;
+        Ok(MotorRow { index, row })
+    }
+}
+
+// channel,min,mid,max (us)
+struct ChannelCal {
+    channel: usize,
+    calibration: rc::Calibration,
+}
+
+impl core::str::FromStr for ChannelCal {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let channel = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        if channel >= 4 {
+            return Err(());
+        }
+        let mut values = [0.; 3];
+        parse_floats(parts, &mut values)?;
+        let [min, mid, max] = values;
+        if !(min < mid && mid < max) {
+            return Err(());
+        }
+        Ok(ChannelCal {
+            channel,
+            calibration: rc::Calibration { min, mid, max },
+        })
+    }
+}
+
+// slot,action,aux,min,max (us) or slot,off
+struct ModeRange {
+    slot: usize,
+    range: Option<modes::Range>,
+}
+
+impl core::str::FromStr for ModeRange {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let slot = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        if slot >= modes::MAX_RANGES {
+            return Err(());
+        }
+        let action = unquote(parts.next().ok_or(())?)?;
+        if action == "off" {
+            return match parts.next() {
+                Some(_) => Err(()),
+                None => Ok(ModeRange { slot, range: None }),
+            };
+        }
+        let action = action.parse()?;
+        let aux: usize = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        let min: u16 = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        let max: u16 = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+        if parts.next().is_some()
+            || aux >= rc::MAX_CHANNELS - modes::FIRST_AUX
+            || min > max
+        {
+            return Err(());
+        }
+        Ok(ModeRange {
+            slot,
+            range: Some(modes::Range {
+                action,
+                aux,
+                min,
+                max,
+            }),
+        })
+    }
+}
+
+// roll,pitch,yaw,thrust[,aux...] (us), virtual receiver
+struct VirtualRc {
+    values: [u16; rc::MAX_CHANNELS],
+    count: usize,
+}
+
+impl VirtualRc {
+    // channels in receiver order, as configured mapping expects
+    fn input(&self, map: rc::ChannelMap, loops: u32) -> rc::Input {
+        let mut input = rc::Input::new();
+        input.virtual_at = Some(loops);
+        let [roll, pitch, thrust, yaw] = map.indices();
+        input.channels[roll] = self.values[0];
+        input.channels[pitch] = self.values[1];
+        input.channels[yaw] = self.values[2];
+        input.channels[thrust] = self.values[3];
+        input.channels[4..self.count]
+            .copy_from_slice(&self.values[4..self.count]);
+        input.count = self.count;
+        input
+    }
+}
+
+impl core::str::FromStr for VirtualRc {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut values = [0; rc::MAX_CHANNELS];
+        let mut count = 0;
+        for part in s.split(',') {
+            if count == rc::MAX_CHANNELS {
+                return Err(());
+            }
+            values[count] = part.parse().map_err(|_| ())?;
+            count += 1;
+        }
+        if count < 4 {
+            return Err(());
+        }
+        Ok(VirtualRc { values, count })
+    }
+}
+
+// roll,pitch,yaw or single value for all of them
+struct Axes([f32; 3]);
+
+impl core::str::FromStr for Axes {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut values = [0.; 3];
+        if s.contains(',') {
+            parse_floats(s.split(','), &mut values)?;
+        } else {
+            values = [finite(s)?; 3];
+        }
+        Ok(Axes(values))
+    }
+}
+
+// name=value,value... or word as is if nothing was read back
+fn echo(word: &[u8], applied: &Applied) -> Echo {
+    let mut echo = Echo::new();
+    let name = word.iter().position(|b| *b == b'=');
+    match name {
+        Some(eq) if !applied.is_empty() => {
+            echo.extend_from_slice(&word[..=eq]).ok();
+            for (i, value) in applied.iter().enumerate() {
+                if i > 0 {
+                    echo.push(b',').ok();
+                }
+                let mut b = ryu::Buffer::new();
+                let s = b.format(*value);
+                // whole numbers as sent, without fraction
+                let s = s.strip_suffix(".0").unwrap_or(s);
+                echo.extend_from_slice(s.as_bytes()).ok();
+            }
+        }
+        _ => {
+            let len = word.len().min(echo.capacity());
+            echo.extend_from_slice(&word[..len]).ok();
+        }
+    }
+    echo
 }
 
 macro_rules! parse {
@@ -16,27 +449,67 @@ macro_rules! parse {
         $inp.starts_with($var.as_bytes())
     };
     (@process $inp:ident $code:expr; $var:expr) => {
-        $code
+        {
+            $code;
+            Ok(())
+        }
     };
     (@process $inp:ident $code:expr; $var:expr, $name:ident : $ty:ty) => {
-
-        let rest = &$inp[$var.len()..];
-        if let Ok($name) = parse::<$ty, _>(rest) {
-            $code
+        {
+            let rest = &$inp[$var.len()..];
+            if let Ok($name) = parse::<$ty>(rest) {
+                $code;
+                Ok(())
+            } else {
+                Err(Error::Malformed)
+            }
         }
     };
+    // evaluates to Ok if input was recognized and applied
     ($input:ident:
      $([$($option:tt)+] => $code:expr),+
     ) => {
         $(
             if (parse!(@cond $input $($option)+)) {
-                parse!(@process $input $code; $($option)+);
+                parse!(@process $input $code; $($option)+)
             } else
         )+
-        { }
+        { Err(Error::Unknown) }
     };
 }
 
+// Commands changing which motor or stick does what, refused in flight
+const DISARMED_ONLY: [&str; 5] =
+    ["mix=", "mixn=", "mixrow=", "rcmap=", "rccal="];
+// Commands starting bench tests, see motortest::allowed
+const MOTOR_TESTS: [&str; 3] = ["mt=", "esccal", "escdir"];
+
+// Command name with value or exact word
+fn is_command(word: &[u8], name: &str) -> bool {
+    if name.ends_with('=') {
+        word.starts_with(name.as_bytes())
+    } else {
+        word == name.as_bytes()
+    }
+}
+
+// Reason to refuse command in current state
+fn refusal(
+    word: &[u8],
+    control: &types::Control,
+    state: &types::State,
+) -> Option<Error> {
+    let disarmed_only = DISARMED_ONLY.iter().any(|c| is_command(word, c));
+    let motor_test = MOTOR_TESTS.iter().any(|c| is_command(word, c));
+    if state.armed && (disarmed_only || motor_test) {
+        Some(Error::Armed)
+    } else if motor_test && !motortest::allowed(state, control) {
+        Some(Error::Props)
+    } else {
+        None
+    }
+}
+
 const BUFFER_SIZE: usize = 512;
 const CR: u8 = b'\r';
 const LF: u8 = b'\n';
@@ -81,53 +554,377 @@ impl Cmd {
         &mut self,
         byte: u8,
         control: &mut types::Control,
-    ) -> Option<types::Requests> {
+        state: &types::State,
+    ) -> Option<Reply> {
         let mut requests = None;
+        let mut applied = Applied::new();
+        let mut reply = None;
         if let Some(word) = self.push(byte) {
             // XXX: maybe return new control, instead of mutating?
-            parse!(word:
-                   ["tmon"] => {
-                       control.telemetry = true;
-                   },
-                   ["tmoff"] => {
-                       control.telemetry = false;
-                   },
-                   ["pk=", pk:i32] => {
-                       control.pk = pk as f32;
-                   },
-                   ["ik=", ik:i32] => {
-                       control.ik = ik as f32;
-                   },
-                   ["dk=", dk:i32] => {
-                       control.dk = dk as f32;
-                   },
-                   ["pipk=", pitch_pk:i32] => {
-                       control.pitch_pk = pitch_pk as f32;
-                   },
-                   ["rpk=", roll_pk:i32] => {
-                       control.roll_pk = roll_pk as f32;
-                   },
-                   ["ypk=", yaw_pk:i32] => {
-                       control.yaw_pk = yaw_pk as f32;
-                   },
-                   ["tthurst=", thrust:i32] => {
-                       control.thrust = thrust as f32;
-                   },
-                   ["pt=", pt:i32] => {
-                       control.target_degrees.pitch = pt as f32;
-                   },
-                   ["status"] => {
-                       requests = Some(types::Requests::Status);
-                   },
-                   ["boot"] => {
-                       requests = Some(types::Requests::Boot);
-                   },
-                   ["reset"] => {
-                       requests = Some(types::Requests::Reset);
-                   }
-            );
-        }
-
-        requests
+            let result = if let Some(error) = refusal(word, control, state) {
+                Err(error)
+            } else {
+                parse!(word:
+                       ["tmon"] => {
+                           control.telemetry = true;
+                       },
+                       ["tmoff"] => {
+                           control.telemetry = false;
+                       },
+                       ["arm"] => {
+                           control.arm = true;
+                       },
+                       ["disarm"] => {
+                           control.arm = false;
+                       },
+                       ["mode=", mode:types::FlightMode] => {
+                           control.mode = mode;
+                       },
+                       ["mix=", preset:Preset] => {
+                           control.mixer.use_preset(preset);
+                       },
+                       ["air=", airmode:Switch] => {
+                           control.mixer.airmode = airmode.0;
+                       },
+                       // percents
+                       ["tlin=", value:Percent] => {
+                           control.mixer.thrust_linear = value.0 / 100.;
+                           applied.push(value.0).ok();
+                       },
+                       ["idle=", value:Percent] => {
+                           control.mixer.idle = value.0 / 100.;
+                           applied.push(value.0).ok();
+                       },
+                       ["maxout=", value:Percent] => {
+                           control.mixer.max_output = value.0 / 100.;
+                           applied.push(value.0).ok();
+                       },
+                       ["beep=", tone:u8] => {
+                           let command = Command::beep(tone);
+                           control.esc_command = Some(command);
+                           applied.push(command as u8 as f32).ok();
+                       },
+                       ["dir=", direction:SpinDirection] => {
+                           control.esc_command = Some(direction.0);
+                       },
+                       ["3d=", mode_3d:Switch] => {
+                           let command = if mode_3d.0 {
+                               Command::Mode3dOn
+                           } else {
+                               Command::Mode3dOff
+                           };
+                           control.esc_command = Some(command);
+                       },
+                       ["escsave"] => {
+                           control.esc_command = Some(Command::SaveSettings);
+                       },
+                       // props on also stops running motor test
+                       ["propsoff"] => {
+                           control.props_off = true;
+                       },
+                       ["propson"] => {
+                           control.props_off = false;
+                       },
+                       ["mt=", m:MotorSpin] => {
+                           let max_ms = motortest::MAX_SPIN_S * 1000.;
+                           let time_ms = m.time_ms.min(max_ms);
+                           control.motor_test = Some(MotorTest::Spin {
+                               motor: m.motor,
+                               output: m.output / 100.,
+                               time_s: time_ms / 1000.,
+                           });
+                           let spin = [m.motor as f32, m.output, time_ms];
+                           applied.extend_from_slice(&spin).ok();
+                       },
+                       ["esccal"] => {
+                           control.motor_test = Some(MotorTest::Calibrate);
+                       },
+                       ["escdir"] => {
+                           control.motor_test = Some(MotorTest::Direction);
+                       },
+                       ["mixn=", m:MotorCount] => {
+                           control.mixer.geometry.motors = m.0;
+                           let motors = control.mixer.geometry.motors;
+                           applied.push(motors as f32).ok();
+                       },
+                       ["mixrow=", m:MotorRow] => {
+                           control.mixer.geometry.rows[m.index] = m.row;
+                           applied.push(m.index as f32).ok();
+                           let row = &control.mixer.geometry.rows[m.index];
+                           applied.extend_from_slice(row).ok();
+                       },
+                       // shared roll and pitch gains
+                       ["pk=", pk:Finite] => {
+                           control.gains[0].p = pk.0;
+                           control.gains[1].p = pk.0;
+                           applied.push(control.gains[0].p).ok();
+                       },
+                       ["ik=", ik:Finite] => {
+                           control.gains[0].i = ik.0;
+                           control.gains[1].i = ik.0;
+                           applied.push(control.gains[0].i).ok();
+                       },
+                       ["dk=", dk:Finite] => {
+                           control.gains[0].d = dk.0;
+                           control.gains[1].d = dk.0;
+                           applied.push(control.gains[0].d).ok();
+                       },
+                       ["rollp=", roll_p:Finite] => {
+                           control.gains[0].p = roll_p.0;
+                           applied.push(control.gains[0].p).ok();
+                       },
+                       ["rolli=", roll_i:Finite] => {
+                           control.gains[0].i = roll_i.0;
+                           applied.push(control.gains[0].i).ok();
+                       },
+                       ["rolld=", roll_d:Finite] => {
+                           control.gains[0].d = roll_d.0;
+                           applied.push(control.gains[0].d).ok();
+                       },
+                       ["rollf=", roll_ff:Finite] => {
+                           control.gains[0].ff = roll_ff.0;
+                           applied.push(control.gains[0].ff).ok();
+                       },
+                       ["pitchp=", pitch_p:Finite] => {
+                           control.gains[1].p = pitch_p.0;
+                           applied.push(control.gains[1].p).ok();
+                       },
+                       ["pitchi=", pitch_i:Finite] => {
+                           control.gains[1].i = pitch_i.0;
+                           applied.push(control.gains[1].i).ok();
+                       },
+                       ["pitchd=", pitch_d:Finite] => {
+                           control.gains[1].d = pitch_d.0;
+                           applied.push(control.gains[1].d).ok();
+                       },
+                       ["pitchf=", pitch_ff:Finite] => {
+                           control.gains[1].ff = pitch_ff.0;
+                           applied.push(control.gains[1].ff).ok();
+                       },
+                       ["yawp=", yaw_p:Finite] => {
+                           control.gains[2].p = yaw_p.0;
+                           applied.push(control.gains[2].p).ok();
+                       },
+                       ["yawi=", yaw_i:Finite] => {
+                           control.gains[2].i = yaw_i.0;
+                           applied.push(control.gains[2].i).ok();
+                       },
+                       ["yawd=", yaw_d:Finite] => {
+                           control.gains[2].d = yaw_d.0;
+                           applied.push(control.gains[2].d).ok();
+                       },
+                       ["yawf=", yaw_ff:Finite] => {
+                           control.gains[2].ff = yaw_ff.0;
+                           applied.push(control.gains[2].ff).ok();
+                       },
+                       ["pipk=", pitch_pk:Finite] => {
+                           control.pitch_pk = pitch_pk.0;
+                           applied.push(control.pitch_pk).ok();
+                       },
+                       ["rpk=", roll_pk:Finite] => {
+                           control.roll_pk = roll_pk.0;
+                           applied.push(control.roll_pk).ok();
+                       },
+                       ["ypk=", yaw_pk:Finite] => {
+                           control.yaw_pk = yaw_pk.0;
+                           applied.push(control.yaw_pk).ok();
+                       },
+                       ["maxr=", max_rate:NonNegative] => {
+                           control.max_rate = max_rate.0;
+                           applied.push(control.max_rate).ok();
+                       },
+                       ["hzt=", horizon_transition:NonNegative] => {
+                           control.horizon_transition = horizon_transition.0;
+                           applied.push(control.horizon_transition).ok();
+                       },
+                       ["poles=", value:NonNegative] => {
+                           control.motor_poles = value.0;
+                           applied.push(control.motor_poles).ok();
+                       },
+                       ["rpmq=", value:NonNegative] => {
+                           control.rpm_q = value.0;
+                           applied.push(control.rpm_q).ok();
+                       },
+                       ["rpmmin=", value:NonNegative] => {
+                           control.rpm_min_hz = value.0;
+                           applied.push(control.rpm_min_hz).ok();
+                       },
+                       ["dflt=", dterm_filter:filters::Kind] => {
+                           control.dterm_filter = dterm_filter;
+                       },
+                       ["dhz=", dterm_cutoff:NonNegative] => {
+                           control.dterm_cutoff = dterm_cutoff.0;
+                           applied.push(control.dterm_cutoff).ok();
+                       },
+                       ["athr=", arm_thrust:NonNegative] => {
+                           control.arm_thrust = arm_thrust.0;
+                           applied.push(control.arm_thrust).ok();
+                       },
+                       ["atilt=", arm_max_tilt:NonNegative] => {
+                           control.arm_max_tilt = arm_max_tilt.0;
+                           applied.push(control.arm_max_tilt).ok();
+                       },
+                       ["lto=", link_timeout:NonNegative] => {
+                           // milliseconds
+                           control.link_timeout = link_timeout.0 / 1000.;
+                           applied.push(link_timeout.0).ok();
+                       },
+                       ["fslvl=", fs_level_time:NonNegative] => {
+                           control.fs_level_time = fs_level_time.0 / 1000.;
+                           applied.push(fs_level_time.0).ok();
+                       },
+                       ["fsdsc=", fs_descent_time:NonNegative] => {
+                           control.fs_descent_time = fs_descent_time.0 / 1000.;
+                           applied.push(fs_descent_time.0).ok();
+                       },
+                       // battery: divider ratio and amps per volt in
+                       // hundredths, offset and cell thresholds in mV
+                       ["vscale=", value:NonNegative] => {
+                           control.battery.voltage_scale = value.0 / 100.;
+                           applied.push(value.0).ok();
+                       },
+                       ["cscale=", value:NonNegative] => {
+                           control.battery.current_scale = value.0 / 100.;
+                           applied.push(value.0).ok();
+                       },
+                       ["coffs=", value:Finite] => {
+                           control.battery.current_offset = value.0 / 1000.;
+                           applied.push(value.0).ok();
+                       },
+                       ["cells=", cells:u8] => {
+                           control.battery.cells = cells;
+                           applied.push(control.battery.cells as f32).ok();
+                       },
+                       ["vwarn=", value:NonNegative] => {
+                           control.battery.warning_v = value.0 / 1000.;
+                           applied.push(value.0).ok();
+                       },
+                       ["vcrit=", value:NonNegative] => {
+                           control.battery.critical_v = value.0 / 1000.;
+                           applied.push(value.0).ok();
+                       },
+                       ["vcomp=", compensation:Switch] => {
+                           control.battery.compensation = compensation.0;
+                       },
+                       ["vnom=", value:NonNegative] => {
+                           control.battery.nominal_v = value.0 / 1000.;
+                           applied.push(value.0).ok();
+                       },
+                       ["rcthr=", value:NonNegative] => {
+                           control.rc.max_thrust = value.0;
+                           applied.push(control.rc.max_thrust).ok();
+                       },
+                       ["rcang=", value:NonNegative] => {
+                           control.rc.max_angle = value.0;
+                           applied.push(control.rc.max_angle).ok();
+                       },
+                       ["rcrate=", rates:Axes] => {
+                           control.rc.rates = rates.0;
+                           applied.extend_from_slice(&control.rc.rates).ok();
+                       },
+                       // percent
+                       ["rcexpo=", expo:Axes] => {
+                           let expos = control.rc.expo.iter_mut();
+                           for (e, v) in expos.zip(&expo.0) {
+                               let percent = clamp(*v, 0., 100.);
+                               *e = percent / 100.;
+                               applied.push(percent).ok();
+                           }
+                       },
+                       ["rcdb=", value:Finite] => {
+                           control.rc.deadband = value.0.max(0.);
+                           applied.push(control.rc.deadband).ok();
+                       },
+                       ["rccal=", c:ChannelCal] => {
+                           control.rc.calibration[c.channel] = c.calibration;
+                           let cal = &control.rc.calibration[c.channel];
+                           let channel = c.channel as f32;
+                           let values = [channel, cal.min, cal.mid, cal.max];
+                           applied.extend_from_slice(&values).ok();
+                       },
+                       // same path as receiver, keeps link alive
+                       ["rc=", v:VirtualRc] => {
+                           let input = v.input(control.rc.map, state.loops);
+                           rc::update(input, control);
+                           // back in command order
+                           let map = control.rc.map;
+                           let [roll, pitch, thrust, yaw] = map.indices();
+                           let order = [roll, pitch, yaw, thrust];
+                           let input = &control.rc_input;
+                           let aux = 4..input.count;
+                           for channel in order.iter().copied().chain(aux) {
+                               let value = input.channels[channel];
+                               applied.push(value as f32).ok();
+                           }
+                       },
+                       ["range=", m:ModeRange] => {
+                           control.ranges[m.slot] = m.range;
+                           control.seen_off[m.slot] = false;
+                       },
+                       ["rcmap=", map:rc::ChannelMap] => {
+                           control.rc.map = map;
+                       },
+                       ["rclq=", value:u8] => {
+                           control.rc.min_link_quality = value;
+                           let quality = control.rc.min_link_quality;
+                           applied.push(quality as f32).ok();
+                       },
+                       ["ilim=", i_limit:NonNegative] => {
+                           control.i_limit = i_limit.0;
+                           applied.push(control.i_limit).ok();
+                       },
+                       ["ithr=", i_thrust:NonNegative] => {
+                           control.i_thrust = i_thrust.0;
+                           applied.push(control.i_thrust).ok();
+                       },
+                       ["tthurst=", thrust:NonNegative] => {
+                           control.thrust = thrust.0;
+                           applied.push(control.thrust).ok();
+                       },
+                       ["pt=", pt:Finite] => {
+                           control.target_degrees.pitch = pt.0;
+                           applied.push(control.target_degrees.pitch).ok();
+                       },
+                       ["rt=", rt:Finite] => {
+                           control.target_degrees.roll = rt.0;
+                           applied.push(control.target_degrees.roll).ok();
+                       },
+                       ["rr=", rr:Finite] => {
+                           control.target_rates.roll = rr.0;
+                           applied.push(control.target_rates.roll).ok();
+                       },
+                       ["pr=", pr:Finite] => {
+                           control.target_rates.pitch = pr.0;
+                           applied.push(control.target_rates.pitch).ok();
+                       },
+                       ["yr=", yr:Finite] => {
+                           control.target_rates.yaw = yr.0;
+                           applied.push(control.target_rates.yaw).ok();
+                       },
+                       ["status"] => {
+                           requests = Some(types::Requests::Status);
+                       },
+                       ["boot"] => {
+                           requests = Some(types::Requests::Boot);
+                       },
+                       ["reset"] => {
+                           requests = Some(types::Requests::Reset);
+                       },
+                       // does nothing, keeps link alive
+                       ["hb"] => {}
+                )
+            };
+            if result.is_ok() {
+                control.link_seq = control.link_seq.wrapping_add(1);
+            }
+            let echo = echo(word, &applied);
+            reply = Some(Reply {
+                result,
+                request: requests,
+                echo,
+            });
+        }
+
+        reply
     }
 }
@@ -1,9 +1,11 @@
 use crate::boards::*;
 
+use ehal::serial::Write;
 use heapless::consts::*;
 use heapless::Vec;
+use nb::block;
 
-pub type TxBuffer = Vec<u8, U256>;
+pub type TxBuffer = Vec<u8, U512>;
 type TxReady = (&'static mut TxBuffer, TxCh, TxUsart);
 type TxBusy = dma::Transfer<dma::R, &'static mut TxBuffer, TxCh, TxUsart>;
 
@@ -33,14 +35,37 @@ impl Channel {
         Channel::with_state(state)
     }
 
-    pub fn send<F>(self, mut buffer_filler: F) -> Self
+    pub fn send<F>(self, buffer_filler: F) -> Self
+    where
+        F: for<'a> FnMut<(&'a mut TxBuffer,), Output = ()>,
+    {
+        self.try_send(buffer_filler).0
+    }
+
+    // Blocks until buffer is out on the wire, e.g. before reset
+    pub fn flush(self) -> Self {
+        let (buffer, ch, mut tx) = match self.state {
+            TransferState::Ready(ready) => ready,
+            TransferState::MaybeBusy(transfer) => {
+                let (buffer, ch, tx) = transfer.wait();
+                buffer.clear();
+                (buffer, ch, tx)
+            }
+        };
+        block!(tx.flush()).ok();
+        Channel::with_state(TransferState::Ready((buffer, ch, tx)))
+    }
+
+    // Same as send, also tells if data was sent or skipped
+    pub fn try_send<F>(self, mut buffer_filler: F) -> (Self, bool)
     where
         F: for<'a> FnMut<(&'a mut TxBuffer,), Output = ()>,
     {
         let ns = match self.state {
             TransferState::Ready((mut buffer, ch, tx)) => {
                 buffer_filler(&mut buffer);
-                TransferState::MaybeBusy(tx.write_all(ch, buffer))
+                let ns = TransferState::MaybeBusy(tx.write_all(ch, buffer));
+                return (Channel::with_state(ns), true);
             }
             TransferState::MaybeBusy(transfer) => {
                 if transfer.is_done() {
@@ -57,9 +82,9 @@ impl Channel {
         };
 
         match ns {
-            TransferState::MaybeBusy(_) => Channel::with_state(ns),
+            TransferState::MaybeBusy(_) => (Channel::with_state(ns), false),
             TransferState::Ready(_) => {
-                Channel::with_state(ns).send(buffer_filler)
+                Channel::with_state(ns).try_send(buffer_filler)
             }
         }
     }
@@ -1,15 +1,54 @@
 use crate::ahrs::AhrsResult;
 use crate::prelude::*;
-use crate::types;
-use crate::utils::to_rads;
+use crate::types::{self, FlightMode};
+use crate::utils::{clamp, to_rads};
 
-// "body rate" controller from f3-eva
-// XXX: return types?
-// returns (corrections, errors)
-pub fn body_rate(
+// "body rate" controller from f3-eva, split into two stages:
+// outer angle loop produces rate setpoint for inner rate loop.
+// updates corrections, errors, integrals and D-term filters in state
+pub fn body_rate(state: &mut types::State, control: &types::Control) {
+    let setpoint = rate_setpoint(state, control);
+    rate(state, control, setpoint)
+}
+
+// returns rate setpoint (rad/s) for selected flight mode
+pub fn rate_setpoint(
     state: &types::State,
     control: &types::Control,
-) -> ([f32; 3], [f32; 3]) {
+) -> [f32; 3] {
+    let rates = [
+        to_rads(control.target_rates.roll),
+        to_rads(control.target_rates.pitch),
+        to_rads(control.target_rates.yaw),
+    ];
+    let level = angle(state, control);
+    match control.mode {
+        FlightMode::Acro => rates,
+        // yaw is always controlled by rate, heading hold is optional
+        FlightMode::Angle => [level[0], level[1], level[2] + rates[2]],
+        FlightMode::Horizon => {
+            // self-leveling fades out as the craft tilts
+            let tilt = libm::fmaxf(
+                libm::fabsf(state.ahrs.ypr.roll),
+                libm::fabsf(state.ahrs.ypr.pitch),
+            );
+            let transition = to_rads(control.horizon_transition);
+            let strength = if transition > 0. {
+                1. - clamp(tilt / transition, 0., 1.)
+            } else {
+                0.
+            };
+            [
+                rates[0] + level[0] * strength,
+                rates[1] + level[1] * strength,
+                rates[2] + level[2],
+            ]
+        }
+    }
+}
+
+// outer loop: angle errors to rates (rad/s)
+fn angle(state: &types::State, control: &types::Control) -> [f32; 3] {
     let pitch_target = to_rads(control.target_degrees.pitch);
     let roll_target = to_rads(control.target_degrees.roll);
     let yaw_target = to_rads(control.target_degrees.yaw);
@@ -18,21 +57,72 @@ pub fn body_rate(
     let yaw_err = (yaw_target - state.ahrs.ypr.yaw) * control.yaw_pk;
     let roll_err = (roll_target - state.ahrs.ypr.roll) * control.roll_pk;
 
-    // XXX?
-    let x_err = roll_err - state.ahrs.biased_gyro[0];
-    let y_err = pitch_err - state.ahrs.biased_gyro[1];
-    let z_err = yaw_err - state.ahrs.biased_gyro[2];
-    // ?XXX
+    let max_rate = to_rads(control.max_rate);
+    [
+        clamp(roll_err, -max_rate, max_rate),
+        clamp(pitch_err, -max_rate, max_rate),
+        clamp(yaw_err, -max_rate, max_rate),
+    ]
+}
 
-    let i_comp = 0.;
-    let delta_x = x_err - state.errors[0];
-    let delta_y = y_err - state.errors[1];
-    let delta_z = z_err - state.errors[2];
-    let x_corr =
-        x_err * control.pk + i_comp * control.ik + control.dk * delta_x;
-    let y_corr =
-        y_err * control.pk + i_comp * control.ik + control.dk * delta_y;
-    let z_corr = 0.; // z_err * control.pk + i_comp * control.ik + control.dk * delta_z;
+// inner loop: rate errors to corrections
+pub fn rate(
+    state: &mut types::State,
+    control: &types::Control,
+    setpoint: [f32; 3],
+) {
+    // integrals are useless (and dangerous) while we are on the ground
+    let grounded = !state.armed || control.thrust < control.i_thrust;
+    let dt_s = state.ahrs.dt_s;
+    // mixer could not deliver last corrections, do not wind up
+    let i_dt_s = if state.saturated { 0. } else { dt_s };
+    for axis in 0..3 {
+        let gains = control.gains[axis];
+        let err = setpoint[axis] - state.ahrs.biased_gyro[axis];
+        let integral = integrate(
+            state.integrals[axis],
+            err,
+            i_dt_s,
+            gains.i,
+            control,
+            grounded,
+        );
+        // D is taken from filtered measurement, not from error,
+        // so setpoint changes do not kick
+        let measured = state.dterm_filters[axis].apply(
+            state.ahrs.biased_gyro[axis],
+            control.dterm_filter,
+            control.dterm_cutoff,
+            dt_s,
+        );
+        let derivative = if dt_s > 0. {
+            (measured - state.dterm_rates[axis]) / dt_s
+        } else {
+            0.
+        };
+        state.cmd[axis] = err * gains.p + integral * gains.i
+            - derivative * gains.d
+            + setpoint[axis] * gains.ff;
+        state.errors[axis] = err;
+        state.integrals[axis] = integral;
+        state.dterm_rates[axis] = measured;
+    }
+}
 
-    ([x_corr, y_corr, z_corr], [x_err, y_err, z_err])
+// Accumulates error over time; accumulator is clamped, so that
+// integral part of correction never exceeds `i_limit`.
+#[inline]
+fn integrate(
+    integral: f32,
+    err: f32,
+    dt_s: f32,
+    ik: f32,
+    control: &types::Control,
+    reset: bool,
+) -> f32 {
+    if reset || ik == 0. {
+        return 0.;
+    }
+    let limit = libm::fabsf(control.i_limit / ik);
+    clamp(integral + err * dt_s, -limit, limit)
 }
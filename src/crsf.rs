@@ -0,0 +1,182 @@
+// CRSF receiver protocol (Crossfire, ExpressLRS): 420000 baud, 8N1.
+// Frame is address, length, type, payload and CRC8 (DVB-S2) over
+// type and payload; length counts type, payload and CRC.
+use crate::rc;
+use crate::types::{self, FlightMode};
+
+pub const MAX_FRAME: usize = 64;
+// frames from receiver are addressed to flight controller,
+// some receivers use transmitter or receiver address instead
+const ADDRESS_FC: u8 = 0xC8;
+const ADDRESS_RECEIVER: u8 = 0xEC;
+const ADDRESS_TX: u8 = 0xEE;
+
+const TYPE_BATTERY: u8 = 0x08;
+const TYPE_LINK_STATISTICS: u8 = 0x14;
+const TYPE_RC_CHANNELS: u8 = 0x16;
+const TYPE_ATTITUDE: u8 = 0x1E;
+const TYPE_FLIGHT_MODE: u8 = 0x21;
+
+const RC_CHANNELS_LEN: usize = 22;
+// uplink rssi 1 and 2, then uplink link quality
+const LINK_QUALITY_OFFSET: usize = 2;
+
+pub struct Crsf {
+    buffer: [u8; MAX_FRAME],
+    pos: usize,
+    // last reported uplink quality, percent
+    link_quality: u8,
+}
+
+pub const fn create() -> Crsf {
+    Crsf::new()
+}
+
+impl Crsf {
+    #[inline]
+    pub const fn new() -> Self {
+        Crsf {
+            buffer: [0; MAX_FRAME],
+            pos: 0,
+            link_quality: 100,
+        }
+    }
+
+    // drops partial frame, e.g. after line error
+    #[inline]
+    pub fn reset(&mut self) {
+        self.pos = 0;
+    }
+
+    // Returns input once complete RC channels frame is received;
+    // link statistics only update link quality of next inputs.
+    pub fn feed(&mut self, byte: u8) -> Option<rc::Input> {
+        if self.pos == 0
+            && byte != ADDRESS_FC
+            && byte != ADDRESS_RECEIVER
+            && byte != ADDRESS_TX
+        {
+            return None;
+        }
+        if self.pos == 1 && (byte < 2 || byte as usize > MAX_FRAME - 2) {
+            self.pos = 0;
+            return None;
+        }
+        self.buffer[self.pos] = byte;
+        self.pos += 1;
+        if self.pos < 2 || self.pos < self.buffer[1] as usize + 2 {
+            return None;
+        }
+        self.pos = 0;
+        let end = self.buffer[1] as usize + 1;
+        let body = &self.buffer[2..end];
+        if crc8(body) != self.buffer[end] {
+            return None;
+        }
+        let payload = &body[1..];
+        match body[0] {
+            TYPE_RC_CHANNELS if payload.len() >= RC_CHANNELS_LEN => {
+                let mut input = rc::Input::new();
+                rc::unpack(&payload[..RC_CHANNELS_LEN], &mut input);
+                input.link_quality = self.link_quality;
+                Some(input)
+            }
+            TYPE_LINK_STATISTICS if payload.len() > LINK_QUALITY_OFFSET => {
+                self.link_quality = payload[LINK_QUALITY_OFFSET];
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+// CRC8 with DVB-S2 polynomial, no reflection, zero initial value
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data.iter() {
+        crc ^= *byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0xD5
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// Telemetry frames sent back to transmitter, one at a time in turns
+#[derive(Copy, Clone, PartialEq)]
+pub enum Downlink {
+    Attitude,
+    Battery,
+    FlightMode,
+}
+
+impl Downlink {
+    #[inline]
+    pub fn next(self) -> Self {
+        match self {
+            Downlink::Attitude => Downlink::Battery,
+            Downlink::Battery => Downlink::FlightMode,
+            Downlink::FlightMode => Downlink::Attitude,
+        }
+    }
+}
+
+// Writes downlink frame of given kind into `out`, returns its length
+pub fn downlink(
+    kind: Downlink,
+    state: &types::State,
+    control: &types::Control,
+    out: &mut [u8; MAX_FRAME],
+) -> usize {
+    let mut payload = [0u8; MAX_FRAME - 4];
+    let (frame_type, len) = match kind {
+        Downlink::Attitude => {
+            // radians * 10000
+            let ypr = state.ahrs.ypr;
+            let angles = [ypr.pitch, ypr.roll, ypr.yaw];
+            for (i, a) in angles.iter().enumerate() {
+                let value = (a * 10000.) as i16;
+                payload[i * 2..i * 2 + 2].copy_from_slice(&value.to_be_bytes());
+            }
+            (TYPE_ATTITUDE, 6)
+        }
+        Downlink::Battery => {
+            // decivolts, deciamperes, mAh (24 bits), remaining percent;
+            // remaining capacity is not estimated
+            let battery = &state.battery;
+            let voltage = (battery.voltage * 10.) as u16;
+            let current = (battery.current.max(0.) * 10.) as u16;
+            let mah = (battery.mah.max(0.) as u32).to_be_bytes();
+            payload[0..2].copy_from_slice(&voltage.to_be_bytes());
+            payload[2..4].copy_from_slice(&current.to_be_bytes());
+            payload[4..7].copy_from_slice(&mah[1..]);
+            (TYPE_BATTERY, 8)
+        }
+        Downlink::FlightMode => {
+            // null terminated, "*" marks disarmed as Betaflight does
+            let mode: &[u8] = match (state.failsafe, control.mode) {
+                (types::Failsafe::Ok, FlightMode::Acro) => b"ACRO",
+                (types::Failsafe::Ok, FlightMode::Angle) => b"ANGL",
+                (types::Failsafe::Ok, FlightMode::Horizon) => b"HOR",
+                _ => b"!FS!",
+            };
+            let mut len = mode.len();
+            payload[..len].copy_from_slice(mode);
+            if !state.armed {
+                payload[len] = b'*';
+                len += 1;
+            }
+            (TYPE_FLIGHT_MODE, len + 1)
+        }
+    };
+    out[0] = ADDRESS_FC;
+    out[1] = len as u8 + 2;
+    out[2] = frame_type;
+    out[3..3 + len].copy_from_slice(&payload[..len]);
+    out[3 + len] = crc8(&out[2..3 + len]);
+    len + 4
+}
@@ -0,0 +1,497 @@
+// DShot digital ESC protocol.
+// Frame is 16 bits, MSB first: 11 bits of value, telemetry request bit
+// and 4 bits of CRC. Values 1-47 are commands, 48-2047 are throttle.
+// Bidirectional variant inverts the line and the CRC, and ESC answers
+// every frame on the same line with GCR encoded eRPM.
+use crate::utils::clamp;
+
+pub const BITS: usize = 16;
+pub const MIN_THROTTLE: u16 = 48;
+pub const MAX_THROTTLE: u16 = 2047;
+
+#[cfg(any(esc = "esc_dshot300", esc = "esc_bdshot300"))]
+pub const BITRATE: u32 = 300_000;
+#[cfg(any(esc = "esc_dshot600", esc = "esc_bdshot600"))]
+pub const BITRATE: u32 = 600_000;
+
+pub const BIDIRECTIONAL: bool =
+    cfg!(any(esc = "esc_bdshot300", esc = "esc_bdshot600"));
+
+// Reply is sent at 5/4 of bitrate: start bit and 20 bits of GCR
+pub const REPLY_BITS: usize = 21;
+// samples per reply bit
+pub const OVERSAMPLE: usize = 3;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Command {
+    MotorStop = 0,
+    Beep1 = 1,
+    Beep2 = 2,
+    Beep3 = 3,
+    Beep4 = 4,
+    Beep5 = 5,
+    EscInfo = 6,
+    SpinDirection1 = 7,
+    SpinDirection2 = 8,
+    Mode3dOff = 9,
+    Mode3dOn = 10,
+    SaveSettings = 12,
+    SpinDirectionNormal = 20,
+    SpinDirectionReversed = 21,
+}
+
+impl Command {
+    // settings are only accepted after several identical frames
+    #[inline]
+    pub fn repeats(&self) -> u8 {
+        match self {
+            Command::SpinDirection1
+            | Command::SpinDirection2
+            | Command::Mode3dOff
+            | Command::Mode3dOn
+            | Command::SaveSettings
+            | Command::SpinDirectionNormal
+            | Command::SpinDirectionReversed => 10,
+            _ => 1,
+        }
+    }
+
+    #[inline]
+    pub fn beep(tone: u8) -> Self {
+        match tone {
+            1 => Command::Beep1,
+            2 => Command::Beep2,
+            3 => Command::Beep3,
+            4 => Command::Beep4,
+            _ => Command::Beep5,
+        }
+    }
+}
+
+#[inline]
+pub fn frame(value: u16, telemetry: bool, bidirectional: bool) -> u16 {
+    let packet = (value << 1) | telemetry as u16;
+    let mut crc = packet ^ (packet >> 4) ^ (packet >> 8);
+    if bidirectional {
+        crc = !crc;
+    }
+    (packet << 4) | (crc & 0x0F)
+}
+
+// Maps duty in [0, max_duty] to throttle value; zero stops the motor
+#[inline]
+pub fn throttle(duty: f32, max_duty: f32) -> u16 {
+    if duty <= 0. {
+        return Command::MotorStop as u16;
+    }
+    let range = (MAX_THROTTLE - MIN_THROTTLE) as f32;
+    MIN_THROTTLE + clamp(duty / max_duty * range, 0., range) as u16
+}
+
+// Timer compare values for zero and one bits for given timer period
+#[derive(Copy, Clone)]
+pub struct Timing {
+    pub bit0: u32,
+    pub bit1: u32,
+}
+
+impl Timing {
+    #[inline]
+    pub fn new(period: u32) -> Self {
+        Timing {
+            bit0: period * 3 / 8,
+            bit1: period * 3 / 4,
+        }
+    }
+}
+
+// Writes compare values of `frame` into interleaved DMA burst buffer:
+// one word per channel per bit.
+pub fn encode(
+    frame: u16,
+    timing: Timing,
+    buffer: &mut [u32],
+    channel: usize,
+    channels: usize,
+) {
+    for bit in 0..BITS {
+        buffer[bit * channels + channel] = if frame & (0x8000 >> bit) != 0 {
+            timing.bit1
+        } else {
+            timing.bit0
+        };
+    }
+}
+
+// Recovers line levels of reply from samples of pin (bit `pin` of
+// every sample) taken at OVERSAMPLE times reply bitrate.
+// Line idles high, reply starts with low start bit.
+pub fn reply_bits(samples: &[u16], pin: u8) -> Option<u32> {
+    let high = |sample: &u16| sample & (1 << pin) != 0;
+    let start = samples.iter().position(|s| !high(s))?;
+    let mut value = 0u32;
+    let mut bits = 0;
+    let mut level = false;
+    let mut run = 0;
+    for sample in samples[start..].iter() {
+        if high(sample) == level {
+            run += 1;
+            continue;
+        }
+        let n = ((run + OVERSAMPLE / 2) / OVERSAMPLE).max(1);
+        bits += n;
+        if bits > REPLY_BITS {
+            return None;
+        }
+        value = (value << n) | if level { (1 << n) - 1 } else { 0 };
+        level = !level;
+        run = 1;
+    }
+    // capture ended in the middle of reply
+    if !level {
+        return None;
+    }
+    // trailing ones blend with idle line
+    let n = REPLY_BITS - bits;
+    Some((value << n) | ((1 << n) - 1))
+}
+
+const GCR_INVALID: u8 = 0xFF;
+#[rustfmt::skip]
+const GCR: [u8; 32] = [
+    GCR_INVALID, GCR_INVALID, GCR_INVALID, GCR_INVALID,
+    GCR_INVALID, GCR_INVALID, GCR_INVALID, GCR_INVALID,
+    GCR_INVALID, 0x9, 0xA, 0xB, GCR_INVALID, 0xD, 0xE, 0xF,
+    GCR_INVALID, GCR_INVALID, 0x2, 0x3, GCR_INVALID, 0x5, 0x6, 0x7,
+    GCR_INVALID, 0x0, 0x8, 0x1, GCR_INVALID, 0x4, 0xC, GCR_INVALID,
+];
+
+// Decodes reply line levels into 12 bits of payload, checking CRC
+pub fn decode_reply(bits: u32) -> Option<u16> {
+    // transition means one, no transition means zero
+    let gcr = (bits ^ (bits >> 1)) & 0xF_FFFF;
+    let mut decoded = 0u16;
+    for quintet in (0..4).rev() {
+        let nibble = GCR[((gcr >> (quintet * 5)) & 0x1F) as usize];
+        if nibble == GCR_INVALID {
+            return None;
+        }
+        decoded = (decoded << 4) | nibble as u16;
+    }
+    let crc = decoded ^ (decoded >> 4) ^ (decoded >> 8) ^ (decoded >> 12);
+    if crc & 0x0F != 0x0F {
+        return None;
+    }
+    Some(decoded >> 4)
+}
+
+// Payload is period of electrical revolution in microseconds,
+// as 9 bit mantissa and 3 bit exponent
+pub fn erpm(payload: u16) -> f32 {
+    // longest period means motor is stopped
+    if payload == 0x0FFF {
+        return 0.;
+    }
+    let period_us = ((payload & 0x01FF) as u32) << (payload >> 9);
+    if period_us == 0 {
+        return 0.;
+    }
+    60_000_000. / period_us as f32
+}
+
+#[cfg(target_os = "none")]
+pub mod stm32f30x {
+    use core::ptr;
+
+    use super::*;
+    use crate::mixer::Outputs;
+    use crate::prelude::*;
+
+    const DMA1_BASE: u32 = 0x4002_0000;
+    const DMA_IFCR: u32 = 0x04;
+    // channel registers: CCR, CNDTR, CPAR, CMAR
+    const DMA_CH_BASE: u32 = 0x08;
+    const DMA_CH_STRIDE: u32 = 0x14;
+    const DMA_CCR: u32 = 0x00;
+    const DMA_CNDTR: u32 = 0x04;
+    const DMA_CPAR: u32 = 0x08;
+    const DMA_CMAR: u32 = 0x0C;
+    // 32 bit memory and peripheral, memory increment, memory to
+    // peripheral, high priority
+    const DMA_CCR_OUT: u32 =
+        (0b10 << 12) | (0b10 << 10) | (0b10 << 8) | (1 << 7) | (1 << 4);
+    // 16 bit memory and peripheral, memory increment, peripheral to
+    // memory, high priority
+    const DMA_CCR_IN: u32 =
+        (0b10 << 12) | (0b01 << 10) | (0b01 << 8) | (1 << 7);
+    const DMA_CCR_EN: u32 = 1;
+    const DMA_CCR_TCIE: u32 = 1 << 1;
+    const TIM2_BASE: u32 = 0x4000_0000;
+    const TIM3_BASE: u32 = 0x4000_0400;
+    const TIM_DIER: u32 = 0x0C;
+    const TIM_DIER_UDE: u32 = 1 << 8;
+    const TIM_CCER: u32 = 0x20;
+    const TIM_ARR: u32 = 0x2C;
+    const TIM_DCR: u32 = 0x48;
+    const TIM_DMAR: u32 = 0x4C;
+    // CCR1 offset in words
+    const TIM_DBA_CCR1: u32 = 0x34 / 4;
+    const GPIOA_BASE: u32 = 0x4800_0000;
+    const GPIO_MODER: u32 = 0x00;
+    const GPIO_IDR: u32 = 0x10;
+
+    // TIM2 drives 4 motors, TIM3 drives 2
+    const TIM2_CHANNELS: usize = 4;
+    const TIM3_CHANNELS: usize = 2;
+    const CHANNELS: usize = TIM2_CHANNELS + TIM3_CHANNELS;
+    // extra slots keep the line idle after the frame
+    const TIM2_LEN: usize = (BITS + 2) * TIM2_CHANNELS;
+    const TIM3_LEN: usize = (BITS + 2) * TIM3_CHANNELS;
+    // DMA1 channel requested by TIM2_UP, also samples replies
+    const TIM2_DMA: u32 = 2;
+    // GPIOA pins of motors, in output order
+    const PINS: [u8; CHANNELS] = [0, 1, 2, 3, 6, 7];
+    // covers ESC turnaround (~30us) and reply at both bitrates
+    const SAMPLES: usize = 160;
+
+    static mut TIM2_BUFFER: [u32; TIM2_LEN] = [0; TIM2_LEN];
+    static mut TIM3_BUFFER: [u32; TIM3_LEN] = [0; TIM3_LEN];
+    static mut SAMPLE_BUFFER: [u16; SAMPLES] = [0; SAMPLES];
+    // TIM2 period while sampling replies, set when DShot is created
+    static mut SAMPLE_PERIOD: u32 = 0;
+
+    // Timer update events trigger DMA burst into CCR1.. registers,
+    // so every update sets duty of next bit for all channels at once.
+    struct Burst {
+        timer: u32,
+        dma_channel: u32,
+        buffer: *mut u32,
+        len: usize,
+    }
+
+    impl Burst {
+        fn new(
+            timer: u32,
+            dma_channel: u32,
+            buffer: *mut u32,
+            len: usize,
+            channels: usize,
+        ) -> Self {
+            let dbl = channels as u32 - 1;
+            unsafe {
+                write(timer + TIM_DCR, (dbl << 8) | TIM_DBA_CCR1);
+                let dier = read(timer + TIM_DIER);
+                write(timer + TIM_DIER, dier | TIM_DIER_UDE);
+                if BIDIRECTIONAL {
+                    // active low outputs, line idles high
+                    let ccer = read(timer + TIM_CCER);
+                    let polarity =
+                        (0..channels).fold(0, |p, ch| p | (1 << (ch * 4 + 1)));
+                    write(timer + TIM_CCER, ccer | polarity);
+                }
+            }
+            Burst {
+                timer,
+                dma_channel,
+                buffer,
+                len,
+            }
+        }
+
+        // Interrupt of DMA channel fires once frame is sent if `notify`
+        fn start(&mut self, notify: bool) {
+            let dmar = self.timer + TIM_DMAR;
+            let buffer = self.buffer as u32;
+            let ccr = if notify {
+                DMA_CCR_OUT | DMA_CCR_TCIE
+            } else {
+                DMA_CCR_OUT
+            };
+            transfer(self.dma_channel, ccr, dmar, buffer, self.len);
+        }
+    }
+
+    #[inline]
+    fn dma_reg(channel: u32, offset: u32) -> u32 {
+        DMA1_BASE + DMA_CH_BASE + DMA_CH_STRIDE * (channel - 1) + offset
+    }
+
+    fn transfer(channel: u32, ccr: u32, from: u32, to: u32, len: usize) {
+        unsafe {
+            write(dma_reg(channel, DMA_CCR), 0);
+            // clear all flags of the channel
+            write(DMA1_BASE + DMA_IFCR, 0xF << (4 * (channel - 1)));
+            write(dma_reg(channel, DMA_CPAR), from);
+            write(dma_reg(channel, DMA_CMAR), to);
+            write(dma_reg(channel, DMA_CNDTR), len as u32);
+            write(dma_reg(channel, DMA_CCR), ccr | DMA_CCR_EN);
+        }
+    }
+
+    #[inline]
+    unsafe fn write(address: u32, value: u32) {
+        ptr::write_volatile(address as *mut u32, value);
+    }
+
+    #[inline]
+    unsafe fn read(address: u32) -> u32 {
+        ptr::read_volatile(address as *const u32)
+    }
+
+    // Switches motor pins between timer outputs and inputs
+    fn pins_as_input(input: bool) {
+        let mask = PINS.iter().fold(0, |m, pin| m | (0b11 << (pin * 2)));
+        let af = PINS.iter().fold(0, |m, pin| m | (0b10 << (pin * 2)));
+        unsafe {
+            let moder = read(GPIOA_BASE + GPIO_MODER) & !mask;
+            let mode = if input { moder } else { moder | af };
+            write(GPIOA_BASE + GPIO_MODER, mode);
+        }
+    }
+
+    // Handler of TIM2 DMA channel interrupt, which is only enabled for
+    // bidirectional frames: starts sampling GPIOA on TIM2 updates, sped
+    // up to OVERSAMPLE times reply bitrate. TIM3 frame started right
+    // after TIM2 one, so it is in its idle slots by now as well.
+    pub fn capture() {
+        pins_as_input(true);
+        unsafe {
+            write(TIM2_BASE + TIM_ARR, SAMPLE_PERIOD);
+            let buffer = SAMPLE_BUFFER.as_mut_ptr() as u32;
+            let idr = GPIOA_BASE + GPIO_IDR;
+            transfer(TIM2_DMA, DMA_CCR_IN, idr, buffer, SAMPLES);
+        }
+    }
+
+    pub struct DShot<P> {
+        // pins are configured as PWM by timers; kept to own them
+        pins: P,
+        period: u32,
+        timing: Timing,
+        values: [u16; CHANNELS],
+        command: Option<(Command, u8)>,
+        // last decoded eRPM, None if ESC did not answer properly
+        erpm: [Option<f32>; CHANNELS],
+        // frame was sent, capture() samples the replies
+        capturing: bool,
+        tim2: Burst,
+        tim3: Burst,
+        dma: (dma1::C2, dma1::C3),
+    }
+
+    impl<P> DShot<P> {
+        // Timers have to be configured for PWM at BITRATE,
+        // `period` is their max duty.
+        pub fn new(
+            pins: P,
+            period: u32,
+            tim2_dma: dma1::C2,
+            tim3_dma: dma1::C3,
+        ) -> Self {
+            let tim2 = unsafe {
+                SAMPLE_PERIOD = period * 4 / (5 * OVERSAMPLE as u32);
+                Burst::new(
+                    TIM2_BASE,
+                    TIM2_DMA,
+                    TIM2_BUFFER.as_mut_ptr(),
+                    TIM2_LEN,
+                    TIM2_CHANNELS,
+                )
+            };
+            // DMA1 channel 3 is requested by TIM3_UP
+            let tim3 = unsafe {
+                Burst::new(
+                    TIM3_BASE,
+                    3,
+                    TIM3_BUFFER.as_mut_ptr(),
+                    TIM3_LEN,
+                    TIM3_CHANNELS,
+                )
+            };
+            DShot {
+                pins,
+                period,
+                timing: Timing::new(period),
+                values: [0; CHANNELS],
+                command: None,
+                erpm: [None; CHANNELS],
+                capturing: false,
+                tim2,
+                tim3,
+                dma: (tim2_dma, tim3_dma),
+            }
+        }
+
+        // Decodes replies sampled since last frame and gives
+        // pins back to timers
+        fn receive(&mut self) {
+            if !self.capturing {
+                return;
+            }
+            self.capturing = false;
+            unsafe {
+                write(dma_reg(TIM2_DMA, DMA_CCR), 0);
+                write(TIM2_BASE + TIM_ARR, self.period);
+            }
+            let samples = unsafe { &SAMPLE_BUFFER[..] };
+            for (erpm, pin) in self.erpm.iter_mut().zip(PINS.iter()) {
+                *erpm = reply_bits(samples, *pin)
+                    .and_then(decode_reply)
+                    .map(super::erpm);
+            }
+            pins_as_input(false);
+        }
+    }
+
+    impl<P> Outputs for DShot<P> {
+        fn count(&self) -> usize {
+            CHANNELS
+        }
+
+        fn max_duty(&self) -> f32 {
+            (MAX_THROTTLE - MIN_THROTTLE) as f32
+        }
+
+        fn set(&mut self, index: usize, duty: f32) {
+            self.values[index] = throttle(duty, self.max_duty());
+        }
+
+        fn flush(&mut self) {
+            if BIDIRECTIONAL {
+                self.receive();
+            }
+            let (value, telemetry) = match self.command.take() {
+                Some((command, repeats)) => {
+                    if repeats > 1 {
+                        self.command = Some((command, repeats - 1));
+                    }
+                    // commands require telemetry bit
+                    (Some(command as u16), true)
+                }
+                None => (None, false),
+            };
+            let (tim2, tim3) =
+                unsafe { (&mut TIM2_BUFFER[..], &mut TIM3_BUFFER[..]) };
+            for (nr, v) in self.values.iter().enumerate() {
+                let f = frame(value.unwrap_or(*v), telemetry, BIDIRECTIONAL);
+                if nr < TIM2_CHANNELS {
+                    encode(f, self.timing, tim2, nr, TIM2_CHANNELS);
+                } else {
+                    let ch = nr - TIM2_CHANNELS;
+                    encode(f, self.timing, tim3, ch, TIM3_CHANNELS);
+                }
+            }
+            self.tim2.start(BIDIRECTIONAL);
+            self.tim3.start(false);
+            self.capturing = BIDIRECTIONAL;
+        }
+
+        fn command(&mut self, command: Command) {
+            self.command = Some((command, command.repeats()));
+        }
+
+        fn erpm(&self, index: usize) -> Option<f32> {
+            self.erpm.get(index).copied().flatten()
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use crate::battery;
+use crate::types::{self, Failsafe, FlightMode};
+
+// Transitions waiting for command channel
+pub type Transitions = heapless::spsc::Queue<Failsafe, heapless::consts::U8>;
+
+// Tracks command link and takes over control when link is lost:
+// levels the craft, ramps thrust down and finally disarms.
+// Critical battery skips leveling and starts descent right away;
+// craft in the air is only disarmed once descent time is over.
+// Battery warning is only reported, it does not take over.
+// Returns new phase on transition.
+pub fn update(
+    state: &mut types::State,
+    control: &mut types::Control,
+) -> Option<Failsafe> {
+    let dt_s = state.ahrs.dt_s;
+    let alive = control.link_seq != state.link_seq;
+    state.link_seq = control.link_seq;
+    if alive {
+        state.link_age_s = 0.;
+    } else {
+        state.link_age_s += dt_s;
+    }
+
+    let lost = control.failsafe_switch
+        || control.link_timeout > 0. && state.link_age_s > control.link_timeout;
+    let critical = state.battery.status == battery::Status::Critical;
+    let phase = match state.failsafe {
+        _ if !lost && !critical => Failsafe::Ok,
+        // nothing to protect on the ground
+        Failsafe::Ok if !state.armed => Failsafe::Ok,
+        Failsafe::Ok | Failsafe::Level if critical => {
+            if state.failsafe == Failsafe::Ok {
+                state.failsafe_thrust = control.thrust;
+            }
+            Failsafe::Descend
+        }
+        Failsafe::Ok => {
+            state.failsafe_thrust = control.thrust;
+            Failsafe::Level
+        }
+        Failsafe::Level if state.failsafe_time_s > control.fs_level_time => {
+            Failsafe::Descend
+        }
+        Failsafe::Descend
+            if state.failsafe_time_s > control.fs_descent_time =>
+        {
+            Failsafe::Landed
+        }
+        phase => phase,
+    };
+
+    let transition = if phase != state.failsafe {
+        state.failsafe = phase;
+        state.failsafe_time_s = 0.;
+        Some(phase)
+    } else {
+        state.failsafe_time_s += dt_s;
+        None
+    };
+
+    match phase {
+        Failsafe::Ok => {}
+        Failsafe::Level => level(control, state.failsafe_thrust),
+        Failsafe::Descend => {
+            let left = if control.fs_descent_time > 0. {
+                1. - state.failsafe_time_s / control.fs_descent_time
+            } else {
+                0.
+            };
+            level(control, state.failsafe_thrust * libm::fmaxf(left, 0.));
+        }
+        Failsafe::Landed => {
+            level(control, 0.);
+            control.arm = false;
+        }
+    }
+
+    transition
+}
+
+fn level(control: &mut types::Control, thrust: f32) {
+    control.mode = FlightMode::Angle;
+    control.target_degrees.roll = 0.;
+    control.target_degrees.pitch = 0.;
+    control.target_rates.roll = 0.;
+    control.target_rates.pitch = 0.;
+    control.target_rates.yaw = 0.;
+    control.thrust = thrust;
+}
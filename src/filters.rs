@@ -0,0 +1,229 @@
+use core::f32::consts::PI;
+
+use crate::mixer::MAX_MOTORS;
+
+const BUTTERWORTH_Q: f32 = 0.707_106_77;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Kind {
+    None,
+    Pt1,
+    Biquad,
+}
+
+// First order low-pass
+#[derive(Copy, Clone)]
+pub struct Pt1 {
+    state: f32,
+}
+
+impl Pt1 {
+    #[inline]
+    pub const fn new() -> Self {
+        Pt1 { state: 0.0 }
+    }
+
+    // gain is recomputed on every step, so loop jitter is accounted for
+    #[inline]
+    pub fn apply(&mut self, input: f32, cutoff_hz: f32, dt_s: f32) -> f32 {
+        let rc = 1. / (2. * PI * cutoff_hz);
+        let k = dt_s / (rc + dt_s);
+        self.state += k * (input - self.state);
+        self.state
+    }
+}
+
+// Second order filter, direct form 1
+#[derive(Copy, Clone)]
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    // passthrough until configured
+    #[inline]
+    pub const fn new() -> Self {
+        Biquad {
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    // Coefficients are from RBJ's Audio EQ Cookbook;
+    // filter state is preserved, so it is safe to retune on the fly.
+    pub fn set_lowpass(&mut self, cutoff_hz: f32, sample_hz: f32) {
+        let (sn, cs) = Self::omega(cutoff_hz, sample_hz);
+        let alpha = sn / (2. * BUTTERWORTH_Q);
+        let a0 = 1. + alpha;
+        self.b0 = (1. - cs) / 2. / a0;
+        self.b1 = (1. - cs) / a0;
+        self.b2 = self.b0;
+        self.a1 = -2. * cs / a0;
+        self.a2 = (1. - alpha) / a0;
+    }
+
+    pub fn set_notch(&mut self, center_hz: f32, sample_hz: f32, q: f32) {
+        let (sn, cs) = Self::omega(center_hz, sample_hz);
+        let alpha = sn / (2. * q);
+        let a0 = 1. + alpha;
+        self.b0 = 1. / a0;
+        self.b1 = -2. * cs / a0;
+        self.b2 = self.b0;
+        self.a1 = self.b1;
+        self.a2 = (1. - alpha) / a0;
+    }
+
+    #[inline]
+    fn omega(freq_hz: f32, sample_hz: f32) -> (f32, f32) {
+        let omega = 2. * PI * freq_hz / sample_hz;
+        (libm::sinf(omega), libm::cosf(omega))
+    }
+
+    #[inline]
+    pub fn apply(&mut self, input: f32) -> f32 {
+        let output = self.b0 * input + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = input;
+        self.y2 = self.y1;
+        self.y1 = output;
+        output
+    }
+}
+
+// Low-pass of selectable kind, retuned when settings change
+#[derive(Copy, Clone)]
+pub struct LowPass {
+    kind: Kind,
+    cutoff_hz: f32,
+    pt1: Pt1,
+    biquad: Biquad,
+}
+
+impl LowPass {
+    #[inline]
+    pub const fn new() -> Self {
+        LowPass {
+            kind: Kind::None,
+            cutoff_hz: 0.0,
+            pt1: Pt1::new(),
+            biquad: Biquad::new(),
+        }
+    }
+
+    pub fn apply(
+        &mut self,
+        input: f32,
+        kind: Kind,
+        cutoff_hz: f32,
+        dt_s: f32,
+    ) -> f32 {
+        let sample_hz = 1. / dt_s;
+        // filter is useless above nyquist frequency
+        if cutoff_hz <= 0. || dt_s <= 0. || cutoff_hz >= sample_hz / 2. {
+            return input;
+        }
+        if kind != self.kind || cutoff_hz != self.cutoff_hz {
+            self.kind = kind;
+            self.cutoff_hz = cutoff_hz;
+            self.biquad.set_lowpass(cutoff_hz, sample_hz);
+        }
+        match kind {
+            Kind::None => input,
+            Kind::Pt1 => self.pt1.apply(input, cutoff_hz, dt_s),
+            Kind::Biquad => self.biquad.apply(input),
+        }
+    }
+}
+
+// Failed eRPM replies in a row after which motor counts as stopped
+pub const RPM_MAX_MISSED: u8 = 10;
+// Notch centre stays within these fractions of loop rate: near zero
+// it would cut stick input, near nyquist notch design breaks down
+const RPM_MIN_FRACTION: f32 = 0.02;
+const RPM_MAX_FRACTION: f32 = 0.45;
+
+// Bank of notches following rotation frequency of every motor,
+// one notch per motor and axis
+#[derive(Copy, Clone)]
+pub struct RpmFilter {
+    notches: [[Biquad; 3]; MAX_MOTORS],
+    missed: [u8; MAX_MOTORS],
+}
+
+impl RpmFilter {
+    #[inline]
+    pub const fn new() -> Self {
+        RpmFilter {
+            notches: [[Biquad::new(); 3]; MAX_MOTORS],
+            missed: [0; MAX_MOTORS],
+        }
+    }
+
+    // Keeps last eRPM of motor over a few failed replies, then sets it
+    // to zero, which disables notches of the motor
+    pub fn track(&mut self, motor: usize, reply: Option<f32>, erpm: &mut f32) {
+        let missed = &mut self.missed[motor];
+        match reply {
+            Some(value) => {
+                *missed = 0;
+                *erpm = value;
+            }
+            None if *missed < RPM_MAX_MISSED => *missed += 1,
+            None => *erpm = 0.,
+        }
+    }
+
+    // Motors below `min_hz` are skipped. Gyro is sampled at loop rate,
+    // so tone of faster motors shows up folded below nyquist frequency,
+    // where their notches are moved to.
+    pub fn apply(
+        &mut self,
+        input: [f32; 3],
+        erpm: &[f32],
+        poles: f32,
+        q: f32,
+        min_hz: f32,
+        dt_s: f32,
+    ) -> [f32; 3] {
+        let mut output = input;
+        if dt_s <= 0. || poles <= 0. || q <= 0. {
+            return output;
+        }
+        let sample_hz = 1. / dt_s;
+        for (notches, erpm) in self.notches.iter_mut().zip(erpm) {
+            // one electrical revolution per pair of poles
+            let hz = erpm / (poles / 2.) / 60.;
+            if hz < min_hz {
+                continue;
+            }
+            let hz = (hz - libm::roundf(hz / sample_hz) * sample_hz).abs();
+            if hz < sample_hz * RPM_MIN_FRACTION
+                || hz > sample_hz * RPM_MAX_FRACTION
+            {
+                continue;
+            }
+            for (axis, notch) in notches.iter_mut().enumerate() {
+                notch.set_notch(hz, sample_hz, q);
+                output[axis] = notch.apply(output[axis]);
+            }
+        }
+        output
+    }
+}
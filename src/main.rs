@@ -13,6 +13,8 @@
 #![feature(const_impl_trait)]
 
 mod ahrs;
+mod arming;
+mod battery;
 #[macro_use]
 mod logging;
 mod blackbox;
@@ -22,12 +24,23 @@ mod chrono;
 mod cmd;
 mod communication;
 mod controllers;
+mod crsf;
+mod dshot;
+mod failsafe;
+mod filters;
 mod mixer;
+mod modes;
+mod motortest;
+mod oneshot;
+mod ppm;
 mod prelude;
+mod rc;
+mod sbus;
 mod spsc;
 mod telemetry;
 mod types;
 mod utils;
+mod watchdog;
 
 use core::fmt::Write;
 use cortex_m_rt::{exception, ExceptionFrame};
@@ -42,11 +55,21 @@ use nb::block;
 use rtic::app;
 use rtic::mutex_prelude::TupleExt02;
 
+use battery::Sensor;
 use boards::*;
 use bootloader::Bootloader;
+use chrono::Chrono;
 use mixer::MotorCtrl;
 use prelude::*;
 use telemetry::Telemetry;
+use watchdog::Watchdog;
+
+// MPU sample rate is 1kHz / (1 + sample_rate_divisor)
+const LOOP_PERIOD_S: f32 = 0.004;
+// watchdog is fed from idle while control loop makes progress
+const WATCHDOG_TIMEOUT_MS: u32 = 100;
+// CRSF telemetry frame every 25 loops, all three kinds in 300ms
+const CRSF_DOWNLINK_LOOPS: u32 = 25;
 
 #[app(device = crate::boards::mydevice, peripherals = true)]
 mod app {
@@ -69,15 +92,31 @@ mod app {
         channel: Option<communication::Channel>,
         #[task_local]
         rx: crate::boards::RxUsart,
+        // PPM receiver does not use serial port
+        #[cfg(not(receiver = "receiver_ppm"))]
+        #[task_local]
+        rc_rx: crate::boards::RcRx,
+        #[cfg(not(receiver = "receiver_ppm"))]
+        #[task_local]
+        rc_tx: crate::boards::RcTx,
         #[task_local]
         producer: crate::spsc::Tx,
         #[task_local]
         consumer: crate::spsc::Rx,
         #[task_local]
         motors: crate::boards::Motors,
+        #[task_local]
+        battery_sensor: crate::boards::Battery,
+        #[task_local]
+        loop_timer: chrono::T,
+        #[task_local]
+        watchdog: crate::watchdog::T,
+        #[task_local]
+        #[init(crate::filters::RpmFilter::new())]
+        rpm_filter: crate::filters::RpmFilter,
         #[init(crate::types::Control::new())]
         control: crate::types::Control,
-        #[init(crate::types::State::new())]
+        // late, as it records reset cause
         state: crate::types::State,
         #[init(crate::bootloader::create())]
         bootloader: crate::bootloader::T,
@@ -108,6 +147,20 @@ mod app {
         usart.listen(hal::serial::Event::Rxne);
         let (tx, rx) = usart.split();
 
+        // receiver
+        #[cfg(not(receiver = "receiver_ppm"))]
+        let (rc_tx, rc_rx) = {
+            let mut rc_usart =
+                conf.rc_usart
+                    .serial(conf.rc_usart_pins, Bps(rc::BAUD), clocks);
+            #[cfg(receiver = "receiver_sbus")]
+            sbus::stm32f30x::configure();
+            rc_usart.listen(hal::serial::Event::Rxne);
+            rc_usart.split()
+        };
+        #[cfg(receiver = "receiver_ppm")]
+        ppm::stm32f30x::configure(conf.ppm_pin, clocks);
+
         // SPI1
         let spi = conf.spi.spi(conf.spi_pins, mpu9250::MODE, 1.mhz(), clocks);
         info!(log, "spi ok");
@@ -154,6 +207,7 @@ mod app {
             clocks,
             Hertz(32_000u32),
         );
+        let battery_sensor = boards::setup_battery(conf.battery_pins);
 
         info!(log, "ready");
         ahrs.setup_time();
@@ -162,6 +216,15 @@ mod app {
         let channel = communication::channel(conf.tx_ch, tx);
         let new_channel =
             channel.send(|b| utils::fill_with_str(b, "channel ok\r\n"));
+
+        let loop_timer = chrono::rtfm_stopwatch(clocks.sysclk());
+        let mut state = types::State::new();
+        let mut watchdog = watchdog::create();
+        state.watchdog_reset = watchdog.was_reset();
+        if state.watchdog_reset {
+            error!(log, "reset by watchdog");
+        }
+        watchdog.start(WATCHDOG_TIMEOUT_MS);
         info!(log, "done init");
 
         (
@@ -172,52 +235,138 @@ mod app {
                 log,
                 debug_pin,
                 rx,
+                #[cfg(not(receiver = "receiver_ppm"))]
+                rc_rx,
+                #[cfg(not(receiver = "receiver_ppm"))]
+                rc_tx,
                 producer,
                 consumer,
                 motors,
+                battery_sensor,
+                loop_timer,
+                watchdog,
+                state,
             },
             init::Monotonics(),
         )
     }
 
-    #[idle(resources=[consumer, control, channel, bootloader])]
+    #[idle(resources=[consumer, control, state, channel, bootloader,
+                      watchdog, rc_tx])]
     fn idle(mut ctx: idle::Context) -> ! {
         static mut CMD: cmd::Cmd = cmd::create();
+        static mut REPLIES: cmd::Replies =
+            heapless::spsc::Queue(heapless::i::Queue::new());
         static TELE: telemetry::Telemetry = telemetry::create();
         let idle::Resources {
             mut consumer,
             mut channel,
             mut control,
+            mut state,
             mut bootloader,
+            mut watchdog,
+            #[cfg(not(receiver = "receiver_ppm"))]
+            mut rc_tx,
         } = ctx.resources;
+        let mut fed_loops = 0;
+        #[cfg(receiver = "receiver_crsf")]
+        let mut downlink_loops = 0;
+        #[cfg(receiver = "receiver_crsf")]
+        let mut downlink = crsf::Downlink::Attitude;
         loop {
+            // hung or silent control loop will not feed the dog
+            let loops = state.lock(|s| s.loops);
+            if loops != fed_loops {
+                fed_loops = loops;
+                watchdog.feed();
+            }
+
+            #[cfg(receiver = "receiver_crsf")]
+            if loops.wrapping_sub(downlink_loops) >= CRSF_DOWNLINK_LOOPS {
+                downlink_loops = loops;
+                let current_state = state.lock(|s| *s);
+                let current_control = control.lock(|c| *c);
+                let mut frame = [0; crsf::MAX_FRAME];
+                let len = crsf::downlink(
+                    downlink,
+                    &current_state,
+                    &current_control,
+                    &mut frame,
+                );
+                for b in frame[..len].iter() {
+                    block!(rc_tx.write(*b)).ok();
+                }
+                downlink = downlink.next();
+            }
+
             let maybe_byte = consumer.dequeue();
 
             if let Some(byte) = maybe_byte {
-                let (requests, current_control) = control.lock(|c| {
-                    let requests = CMD.feed(byte, c);
-                    (requests, *c)
+                let (reply, current_control) = state.lock(|s| {
+                    control.lock(|c| {
+                        let reply = CMD.feed(byte, c, s);
+                        (reply, *c)
+                    })
                 });
+                let requests = reply.as_ref().and_then(|r| r.request);
+                if let Some(reply) = reply {
+                    // with full queue reply is lost, sender times out
+                    REPLIES.enqueue(reply).ok();
+                }
                 match requests {
                     Some(types::Requests::Status) => {
+                        let current_state = state.lock(|s| *s);
                         channel.lock(|shared_channel| {
                             let maybe_channel = shared_channel.take();
                             if let Some(channel) = maybe_channel {
-                                let new_channel =
-                                    TELE.control(&current_control, channel);
+                                let new_channel = TELE.status(
+                                    &current_control,
+                                    &current_state,
+                                    channel,
+                                );
                                 *shared_channel = Some(new_channel);
                             }
                         });
                     }
-                    Some(types::Requests::Boot) => {
-                        bootloader.lock(|b| b.to_bootloader());
-                    }
-                    Some(types::Requests::Reset) => {
-                        bootloader.lock(|b| b.system_reset());
+                    Some(request) => {
+                        // boot and reset: replies go out before restart
+                        while let Some(reply) = REPLIES.dequeue() {
+                            channel.lock(|shared_channel| {
+                                if let Some(channel) = shared_channel.take() {
+                                    let channel = channel.flush();
+                                    let (channel, _) =
+                                        TELE.reply(&reply, channel);
+                                    *shared_channel = Some(channel.flush());
+                                }
+                            });
+                        }
+                        bootloader.lock(|b| match request {
+                            types::Requests::Boot => b.to_bootloader(),
+                            _ => b.system_reset(),
+                        });
                     }
-                    _ => {}
+                    None => {}
                 }
             }
+
+            // replies wait in order until channel is free
+            let sent = match REPLIES.iter().next() {
+                Some(reply) => {
+                    channel.lock(|shared_channel| match shared_channel.take() {
+                        Some(channel) => {
+                            let (new_channel, sent) =
+                                TELE.reply(reply, channel);
+                            *shared_channel = Some(new_channel);
+                            sent
+                        }
+                        None => false,
+                    })
+                }
+                None => false,
+            };
+            if sent {
+                REPLIES.dequeue();
+            }
         }
     }
 
@@ -240,35 +389,158 @@ mod app {
         }
     }
 
+    #[task(binds=USART1_EXTI25, resources = [rc_rx, control])]
+    #[cfg(not(receiver = "receiver_ppm"))]
+    fn handle_rc(mut ctx: handle_rc::Context) {
+        static mut PARSER: rc::Parser = rc::Parser::new();
+        let handle_rc::Resources {
+            mut rc_rx,
+            mut control,
+        } = ctx.resources;
+
+        match rc_rx.read() {
+            Ok(b) => {
+                if let Some(input) = PARSER.feed(b) {
+                    control.lock(|c| rc::update(input, c));
+                }
+            }
+            // line error, wait for next frame
+            Err(e) => PARSER.reset(),
+        }
+    }
+
+    #[task(binds=TIM4, resources = [control])]
+    #[cfg(receiver = "receiver_ppm")]
+    fn handle_ppm(mut ctx: handle_ppm::Context) {
+        static mut PPM: ppm::Ppm = ppm::create();
+        let handle_ppm::Resources { mut control } = ctx.resources;
+
+        if let Some(capture) = ppm::stm32f30x::read() {
+            if let Some(input) = PPM.feed(capture) {
+                control.lock(|c| rc::update(input, c));
+            }
+        }
+    }
+
+    // Bidirectional DShot frame is sent, ESC replies within 30us,
+    // so sampling has to start before control loop is done
+    #[task(binds=DMA1_CH2, priority = 2)]
+    fn handle_dshot(ctx: handle_dshot::Context) {
+        dshot::stm32f30x::capture();
+    }
+
     #[task(binds=[("configuration_drone", EXTI15_10),
                   ("configuration_dev", EXTI0)],
            resources = [extih, ahrs, log, debug_pin,
-                        channel, control, state, motors])]
+                        channel, control, state, motors, loop_timer,
+                        rpm_filter, battery_sensor])]
     fn handle_mpu(mut ctx: handle_mpu::Context) {
         static TELE: telemetry::Telemetry = telemetry::create();
+        static mut TRANSITIONS: failsafe::Transitions =
+            heapless::spsc::Queue(heapless::i::Queue::new());
+        let mut loop_timer = ctx.resources.loop_timer;
+        loop_timer.reset();
         let mut debug_pin = ctx.resources.debug_pin;
         let mut ahrs = ctx.resources.ahrs;
         let mut state = ctx.resources.state.lock(|s| s.clone());
         let mut log = ctx.resources.log;
         let mut motors = ctx.resources.motors;
+        let mut rpm_filter = ctx.resources.rpm_filter;
+        let mut battery_sensor = ctx.resources.battery_sensor;
         let mut channel = ctx.resources.channel;
         let mut extih = ctx.resources.extih;
-        let control = ctx.resources.control.lock(|c| c.clone());
+        let mut control = ctx.resources.control.lock(|c| {
+            modes::update(&state, c);
+            rc::expire(&state, c);
+            c.clone()
+        });
 
         let estimation = ahrs.estimate();
         match estimation {
             Ok(result) => {
                 state.ahrs = result;
-                let (cmd, errors) = controllers::body_rate(&state, &control);
-                state.errors = errors;
-                state.cmd = cmd;
+                state.imu_errors = 0;
+                for (nr, erpm) in state.erpm.iter_mut().enumerate() {
+                    rpm_filter.track(nr, motors.erpm(nr), erpm);
+                }
+                state.ahrs.biased_gyro = rpm_filter.apply(
+                    result.biased_gyro,
+                    &state.erpm,
+                    control.motor_poles,
+                    control.rpm_q,
+                    control.rpm_min_hz,
+                    result.dt_s,
+                );
+                let raw = battery_sensor.read();
+                battery::update(&mut state, &control, raw);
+                let transition = failsafe::update(&mut state, &mut control);
+                state.mixer_ok = motors.configure(&control.mixer);
+                if !arming::update(&mut state, &control) || !control.arm {
+                    ctx.resources.control.lock(|c| c.arm = false);
+                }
+                controllers::body_rate(&mut state, &control);
+                let cmd = state.cmd;
+
+                if let Some(command) = control.esc_command {
+                    if !state.armed {
+                        motors.command(command);
+                    }
+                    ctx.resources.control.lock(|c| c.esc_command = None);
+                }
+                if let Some(test) = control.motor_test {
+                    // armed or props on since command was acknowledged
+                    if !motortest::start(&mut state, &control, test) {
+                        log.lock(|l| error!(l, "motor test refused"));
+                    }
+                    ctx.resources.control.lock(|c| c.motor_test = None);
+                }
+                if state.armed {
+                    let k =
+                        battery::compensation(&state.battery, &control.battery);
+                    let thrust = control.thrust * k;
+                    motors.set_duty(cmd[0] * k, cmd[1] * k, cmd[2] * k, thrust);
+                } else if let Some(outputs) =
+                    motortest::update(&mut state, &control)
+                {
+                    motors.set_raw(&outputs);
+                } else {
+                    motors.stop();
+                }
+                state.saturated = motors.saturated();
+
+                let busy_s = loop_timer.split_time_s();
+                watchdog::account(
+                    &mut state,
+                    result.dt_s,
+                    busy_s,
+                    LOOP_PERIOD_S,
+                );
                 ctx.resources.state.lock(|s| {
                     *s = state;
                 });
 
-                motors.set_duty(cmd[0], cmd[1], cmd[2], control.thrust);
-
-                if control.telemetry {
+                if let Some(phase) = transition {
+                    // with full queue latest phase is lost
+                    TRANSITIONS.enqueue(phase).ok();
+                }
+                // transitions wait in order until channel is free,
+                // state lines are skipped meanwhile
+                if let Some(phase) = TRANSITIONS.iter().next() {
+                    let sent = channel.lock(|maybe_channel| {
+                        match maybe_channel.take() {
+                            Some(in_channel) => {
+                                let (new_channel, sent) =
+                                    TELE.failsafe(*phase, in_channel);
+                                *maybe_channel = Some(new_channel);
+                                sent
+                            }
+                            None => false,
+                        }
+                    });
+                    if sent {
+                        TRANSITIONS.dequeue();
+                    }
+                } else if control.telemetry {
                     channel.lock(|maybe_channel| {
                         if let Some(in_channel) = maybe_channel.take() {
                             let new_channel = TELE.state(&state, in_channel);
@@ -288,6 +560,10 @@ mod app {
                 });
             }
             Err(_e) => {
+                state.imu_errors += 1;
+                ctx.resources.state.lock(|s| {
+                    s.imu_errors = state.imu_errors;
+                });
                 log.lock(|l| error!(l, "err"));
             }
         };
@@ -1,63 +1,333 @@
-use crate::boards::*;
-use hal::timer;
+use crate::dshot;
+use crate::utils::clamp;
+
+pub const MAX_MOTORS: usize = 8;
+
+#[cfg(motors = "motors_quad")]
+pub const DEFAULT_PRESET: Preset = Preset::QuadX;
+#[cfg(motors = "motors_hex")]
+pub const DEFAULT_PRESET: Preset = Preset::HexX;
 
 pub trait MotorCtrl {
     fn set_duty(&mut self, x: f32, y: f32, z: f32, thrust: f32);
+
+    fn stop(&mut self) {
+        self.set_duty(0., 0., 0., 0.);
+    }
+
+    /// Returns false if geometry needs more motors than we have outputs
+    fn configure(&mut self, config: &Config) -> bool;
+
+    /// Last set_duty had to cut corrections or shift thrust
+    fn saturated(&self) -> bool {
+        false
+    }
+
+    /// Sets outputs (fractions of max duty) directly, bypassing geometry
+    fn set_raw(&mut self, _outputs: &[f32]) {}
+
+    /// Special ESC command, ignored by analog outputs
+    fn command(&mut self, _command: dshot::Command) {}
+
+    /// Electrical RPM reported by ESC of motor `index`
+    fn erpm(&self, _index: usize) -> Option<f32> {
+        None
+    }
 }
 
 impl MotorCtrl for () {
     // dummy
-    fn set_duty(&mut self, x: f32, y: f32, z: f32, thrust: f32) {}
+    fn set_duty(&mut self, _x: f32, _y: f32, _z: f32, _thrust: f32) {}
+
+    // dummy accepts anything
+    fn configure(&mut self, _config: &Config) -> bool {
+        true
+    }
 }
 
-pub struct Mixer<M, P> {
-    pub map: M,
-    pub pin: P,
-    pub max_duty: f32,
+// Physical motor outputs
+pub trait Outputs {
+    fn count(&self) -> usize;
+    fn max_duty(&self) -> f32;
+    fn set(&mut self, index: usize, duty: f32);
+
+    /// Called once all outputs are set
+    fn flush(&mut self) {}
+
+    fn command(&mut self, _command: dshot::Command) {}
+
+    fn erpm(&self, _index: usize) -> Option<f32> {
+        None
+    }
 }
 
-pub type Map4 = [[f32; 4]; 4];
-pub type Map6 = [[f32; 4]; 6];
+#[derive(Copy, Clone, PartialEq)]
+pub enum Preset {
+    QuadX,
+    QuadPlus,
+    HexX,
+    HexPlus,
+    Y6,
+    OctoX,
+}
 
-macro_rules! impl_motor_ctrl {
-    ($map:ident, $num:expr, $($pin:ident $nr:tt)+) => (
-        impl<$($pin),+> Mixer<$map, ($($pin),+)>
-        where $($pin: ehal::PwmPin<Duty = u32>),+
-        {
-            pub fn get_duty(&mut self) -> [u32; $num] {
-                [ $( self.pin.$nr.get_duty() ),+ ]
-            }
+// roll (x), pitch (y), yaw (z) and thrust coefficients of one motor
+pub type Row = [f32; 4];
+
+#[derive(Copy, Clone)]
+pub struct Geometry {
+    pub rows: [Row; MAX_MOTORS],
+    pub motors: usize,
+}
+
+impl Geometry {
+    #[inline]
+    pub const fn new() -> Self {
+        Geometry {
+            rows: [[0.0; 4]; MAX_MOTORS],
+            motors: 0,
         }
+    }
 
-        impl<$($pin),+> MotorCtrl for Mixer<$map, ($($pin),+)>
-        where $($pin: ehal::PwmPin<Duty = u32>),+ {
-            fn set_duty(&mut self, x: f32, y: f32, z: f32, thrust: f32) {
-                // let duty = self.map * Ctrl::new(x, y, z, thrust);
-                let max_duty = self.max_duty;
-                $(
-                    {
-                        let row = self.map[$nr];
-                        let iduty = row[0] * x + row[1] * y + row[2] * z + row[3] * thrust;
-                        self.pin.$nr.set_duty(clamp(iduty, 0.0, max_duty) as u32);
-                    }
-                )+
+    // Rows are in output order, comments name motor position.
+    // Positive roll is right side, positive pitch is front.
+    #[rustfmt::skip]
+    pub const fn preset(preset: Preset) -> Self {
+        let mut rows = [[0.0; 4]; MAX_MOTORS];
+        let motors = match preset {
+            Preset::QuadX => {
+                // diagonal motors spin in the same direction
+                rows[0] = [ 1., -1., -1., 1.]; /* rear right */
+                rows[1] = [ 1.,  1.,  1., 1.]; /* front right */
+                rows[2] = [-1., -1.,  1., 1.]; /* rear left */
+                rows[3] = [-1.,  1., -1., 1.]; /* front left */
+                4
+            }
+            Preset::QuadPlus => {
+                rows[0] = [ 0., -1., -1., 1.]; /* rear */
+                rows[1] = [ 1.,  0.,  1., 1.]; /* right */
+                rows[2] = [-1.,  0.,  1., 1.]; /* left */
+                rows[3] = [ 0.,  1., -1., 1.]; /* front */
+                4
+            }
+            Preset::HexX => {
+                rows[0] = [ 0.567, -0.815, -1.0, 1.0]; /* rear right */
+                rows[1] = [ 0.567,  0.815, -1.0, 1.0]; /* front right */
+                rows[2] = [-0.567, -0.815,  1.0, 1.0]; /* rear left */
+                rows[3] = [-0.567,  0.815,  1.0, 1.0]; /* front left */
+                rows[4] = [-1.0,   -0.0,   -1.0, 1.0]; /* left */
+                rows[5] = [ 1.0,   -0.0,    1.0, 1.0]; /* right */
+                6
+            }
+            Preset::HexPlus => {
+                rows[0] = [ 0.866, -0.5,  1.0, 1.0]; /* rear right */
+                rows[1] = [ 0.866,  0.5, -1.0, 1.0]; /* front right */
+                rows[2] = [-0.866, -0.5,  1.0, 1.0]; /* rear left */
+                rows[3] = [-0.866,  0.5, -1.0, 1.0]; /* front left */
+                rows[4] = [ 0.0,   -1.0, -1.0, 1.0]; /* rear */
+                rows[5] = [ 0.0,    1.0,  1.0, 1.0]; /* front */
+                6
+            }
+            Preset::Y6 => {
+                rows[0] = [ 0.0, -1.333,  1.0, 1.0]; /* top rear */
+                rows[1] = [ 1.0,  0.666, -1.0, 1.0]; /* top front right */
+                rows[2] = [-1.0,  0.666, -1.0, 1.0]; /* top front left */
+                rows[3] = [ 0.0, -1.333, -1.0, 1.0]; /* bottom rear */
+                rows[4] = [ 1.0,  0.666,  1.0, 1.0]; /* bottom front right */
+                rows[5] = [-1.0,  0.666,  1.0, 1.0]; /* bottom front left */
+                6
+            }
+            Preset::OctoX => {
+                // clockwise, starting from front right
+                rows[0] = [ 0.414,  1.0,    1.0, 1.0];
+                rows[1] = [ 1.0,    0.414, -1.0, 1.0];
+                rows[2] = [ 1.0,   -0.414,  1.0, 1.0];
+                rows[3] = [ 0.414, -1.0,   -1.0, 1.0];
+                rows[4] = [-0.414, -1.0,    1.0, 1.0];
+                rows[5] = [-1.0,   -0.414, -1.0, 1.0];
+                rows[6] = [-1.0,    0.414,  1.0, 1.0];
+                rows[7] = [-0.414,  1.0,   -1.0, 1.0];
+                8
             }
+        };
+        Geometry { rows, motors }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Config {
+    pub geometry: Geometry,
+    // keep attitude authority at zero thrust by adding thrust
+    pub airmode: bool,
+    // 0 for linear thrust, 1 for thrust growing with square of output
+    pub thrust_linear: f32,
+    // output range of armed motors, fractions of max duty
+    pub idle: f32,
+    pub max_output: f32,
+}
+
+impl Config {
+    #[inline]
+    pub const fn new() -> Self {
+        Config {
+            geometry: Geometry::preset(DEFAULT_PRESET),
+            airmode: false,
+            thrust_linear: 0.0,
+            idle: 0.0,
+            max_output: 1.0,
         }
-    )
+    }
+
+    #[inline]
+    pub fn use_preset(&mut self, preset: Preset) {
+        self.geometry = Geometry::preset(preset);
+    }
 }
 
-impl_motor_ctrl!(Map4, 4, A 0 B 1 C 2 D 3);
-impl_motor_ctrl!(Map6, 6, A 0 B 1 C 2 D 3 E 4 F 5);
+pub struct Mixer<O> {
+    pub config: Config,
+    pub out: O,
+    saturated: bool,
+}
+
+impl<O: Outputs> Mixer<O> {
+    pub fn new(out: O) -> Self {
+        Mixer {
+            config: Config::new(),
+            out,
+            saturated: false,
+        }
+    }
+}
 
-#[inline]
-fn clamp<T: PartialOrd>(val: T, min: T, max: T) -> T {
-    if val > min {
-        if val < max {
-            val
-        } else {
-            max
+// Scale of attitude corrections and thrust that keep all motors within
+// [0, max_duty], preserving proportions of corrections.
+// Assumes thrust coefficient of 1 for every motor.
+pub fn desaturate(
+    attitude: &[f32],
+    thrust: f32,
+    max_duty: f32,
+    airmode: bool,
+) -> (f32, f32) {
+    let lo = attitude.iter().fold(0f32, |m, a| m.min(*a));
+    let hi = attitude.iter().fold(0f32, |m, a| m.max(*a));
+    let mut scale = if hi - lo > max_duty {
+        max_duty / (hi - lo)
+    } else {
+        1.
+    };
+    let thrust = if airmode {
+        clamp(thrust, -lo * scale, max_duty - hi * scale)
+    } else {
+        // never add thrust, shrink corrections instead
+        let thrust = thrust.max(0.);
+        if thrust + lo * scale < 0. {
+            scale = thrust / -lo;
         }
+        thrust.min(max_duty - hi * scale)
+    };
+    (scale, thrust)
+}
+
+// Maps motor demand (fraction of full thrust) to output fraction.
+// Output `o` gives thrust `a * o^2 + (1 - a) * o`, which is inverted
+// here, so thrust follows demand; then it is fitted to output range.
+pub fn shape(demand: f32, config: &Config) -> f32 {
+    let demand = clamp(demand, 0., 1.);
+    let a = clamp(config.thrust_linear, 0., 1.);
+    let output = if a > 0. {
+        let b = 1. - a;
+        (libm::sqrtf(b * b + 4. * a * demand) - b) / (2. * a)
     } else {
-        min
+        demand
+    };
+    config.idle + output * (config.max_output - config.idle)
+}
+
+impl<O: Outputs> MotorCtrl for Mixer<O> {
+    fn set_duty(&mut self, x: f32, y: f32, z: f32, thrust: f32) {
+        let max_duty = self.out.max_duty();
+        let geometry = &self.config.geometry;
+        let motors = geometry.motors.min(self.out.count());
+        let mut attitude = [0f32; MAX_MOTORS];
+        let rows = geometry.rows.iter().take(motors);
+        for (a, row) in attitude.iter_mut().zip(rows) {
+            *a = row[0] * x + row[1] * y + row[2] * z;
+        }
+        let (scale, new_thrust) = desaturate(
+            &attitude[..motors],
+            thrust,
+            max_duty,
+            self.config.airmode,
+        );
+        self.saturated = scale < 1. || new_thrust != thrust;
+        // outputs beyond geometry stay at zero
+        let mut duty = [0f32; MAX_MOTORS];
+        let rows = geometry.rows.iter().take(motors);
+        for ((d, a), row) in duty.iter_mut().zip(&attitude).zip(rows) {
+            let demand = a * scale + row[3] * new_thrust;
+            *d = shape(demand / max_duty, &self.config) * max_duty;
+        }
+        for (nr, d) in duty.iter().enumerate().take(self.out.count()) {
+            self.out.set(nr, clamp(*d, 0.0, max_duty));
+        }
+        self.out.flush();
+    }
+
+    // idle is for armed motors only
+    fn stop(&mut self) {
+        self.set_raw(&[]);
+    }
+
+    fn configure(&mut self, config: &Config) -> bool {
+        self.config = *config;
+        config.geometry.motors <= self.out.count()
+    }
+
+    fn saturated(&self) -> bool {
+        self.saturated
+    }
+
+    fn set_raw(&mut self, outputs: &[f32]) {
+        let max_duty = self.out.max_duty();
+        for nr in 0..self.out.count() {
+            let output = outputs.get(nr).copied().unwrap_or(0.);
+            self.out.set(nr, clamp(output, 0., 1.) * max_duty);
+        }
+        self.out.flush();
+    }
+
+    fn command(&mut self, command: dshot::Command) {
+        self.out.command(command);
+    }
+
+    fn erpm(&self, index: usize) -> Option<f32> {
+        self.out.erpm(index)
     }
 }
+
+macro_rules! impl_outputs {
+    ($num:expr, $($pin:ident $nr:tt)+) => (
+        impl<$($pin),+> Outputs for ($($pin),+)
+        where $($pin: ehal::PwmPin<Duty = u32>),+
+        {
+            fn count(&self) -> usize {
+                $num
+            }
+
+            fn max_duty(&self) -> f32 {
+                self.0.get_max_duty() as f32
+            }
+
+            fn set(&mut self, index: usize, duty: f32) {
+                match index {
+                    $( $nr => self.$nr.set_duty(duty as u32), )+
+                    _ => {}
+                }
+            }
+        }
+    )
+}
+
+impl_outputs!(4, A 0 B 1 C 2 D 3);
+impl_outputs!(6, A 0 B 1 C 2 D 3 E 4 F 5);
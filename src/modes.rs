@@ -0,0 +1,140 @@
+use crate::dshot::Command;
+use crate::types::{self, FlightMode};
+
+pub const MAX_RANGES: usize = 8;
+// AUX 0 is first channel after sticks
+pub const FIRST_AUX: usize = 4;
+pub const ACTIONS: usize = 7;
+// ESC beeps once a second while beeper is on
+const BEEPER_LOOPS: u32 = 250;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Action {
+    // arms on switch edge after switch was seen off, disarms while off
+    Arm,
+    Angle,
+    Horizon,
+    Airmode,
+    // state stream, our flight recorder
+    Telemetry,
+    // ESC beeps to find crashed craft, only while disarmed
+    Beeper,
+    // acts as lost link
+    Failsafe,
+}
+
+impl Action {
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Arm => "arm",
+            Action::Angle => "angle",
+            Action::Horizon => "horizon",
+            Action::Airmode => "air",
+            Action::Telemetry => "tele",
+            Action::Beeper => "beeper",
+            Action::Failsafe => "failsafe",
+        }
+    }
+}
+
+impl core::str::FromStr for Action {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let actions = [
+            Action::Arm,
+            Action::Angle,
+            Action::Horizon,
+            Action::Airmode,
+            Action::Telemetry,
+            Action::Beeper,
+            Action::Failsafe,
+        ];
+        actions.iter().find(|a| a.as_str() == s).copied().ok_or(())
+    }
+}
+
+// Action is on while AUX channel is within min..=max microseconds
+#[derive(Copy, Clone)]
+pub struct Range {
+    pub action: Action,
+    pub aux: usize,
+    pub min: u16,
+    pub max: u16,
+}
+
+pub type Ranges = [Option<Range>; MAX_RANGES];
+
+// Switched on actions, indexed by action
+pub type Switches = [bool; ACTIONS];
+
+// Flags per range slot
+pub type Latches = [bool; MAX_RANGES];
+
+// Applies configured ranges to control. Actions without ranges are
+// left to commands. Inputs in receiver failsafe are ignored, so that
+// switches keep their last position until failsafe takes over.
+pub fn update(state: &types::State, control: &mut types::Control) {
+    let input = control.rc_input;
+    if input.failsafe || input.count <= FIRST_AUX {
+        return;
+    }
+    let mut configured = [false; ACTIONS];
+    let mut on = [false; ACTIONS];
+    let ranges = control.ranges.iter().zip(control.seen_off.iter_mut());
+    for (range, seen_off) in ranges {
+        let range = match range {
+            Some(range) => range,
+            None => continue,
+        };
+        let index = range.action as usize;
+        configured[index] = true;
+        let channel = FIRST_AUX + range.aux;
+        if channel < input.count {
+            let us = input.channels[channel];
+            let within = range.min <= us && us <= range.max;
+            *seen_off |= !within;
+            // switch left on at boot or when range is set does not arm
+            let armable = range.action != Action::Arm || *seen_off;
+            on[index] |= within && armable;
+        }
+    }
+    let previous = control.switches;
+    control.switches = on;
+
+    let arm = Action::Arm as usize;
+    if configured[arm] {
+        // no arming with switch left on, e.g. after failsafe
+        if on[arm] && !previous[arm] {
+            control.arm = true;
+        } else if !on[arm] {
+            control.arm = false;
+        }
+    }
+    let (angle, horizon) = (Action::Angle as usize, Action::Horizon as usize);
+    if configured[angle] || configured[horizon] {
+        control.mode = if on[angle] {
+            FlightMode::Angle
+        } else if on[horizon] {
+            FlightMode::Horizon
+        } else {
+            FlightMode::Acro
+        };
+    }
+    let airmode = Action::Airmode as usize;
+    if configured[airmode] {
+        control.mixer.airmode = on[airmode];
+    }
+    let telemetry = Action::Telemetry as usize;
+    if configured[telemetry] {
+        control.telemetry = on[telemetry];
+    }
+    // is_multiple_of is newer than firmware toolchain
+    #[allow(clippy::manual_is_multiple_of)]
+    let beep_loop = state.loops % BEEPER_LOOPS == 0;
+    if on[Action::Beeper as usize] && beep_loop {
+        control.esc_command = Some(Command::beep(3));
+    }
+    control.failsafe_switch = on[Action::Failsafe as usize];
+}
@@ -0,0 +1,82 @@
+use crate::mixer::MAX_MOTORS;
+use crate::types::{self, MotorTest};
+
+// longest single motor spin
+pub const MAX_SPIN_S: f32 = 10.0;
+// ESC needs some time at each endpoint to beep and store it
+const CALIBRATE_HIGH_S: f32 = 4.0;
+const CALIBRATE_LOW_S: f32 = 4.0;
+// motors spin one after another, with pauses in between
+const DIRECTION_OUTPUT: f32 = 0.1;
+const DIRECTION_SPIN_S: f32 = 1.0;
+const DIRECTION_PAUSE_S: f32 = 0.5;
+
+// fractions of max output, in motor output order
+pub type Outputs = [f32; MAX_MOTORS];
+
+// Bench tests are only allowed on the ground, with props removed
+#[inline]
+pub fn allowed(state: &types::State, control: &types::Control) -> bool {
+    !state.armed && control.props_off
+}
+
+// Starts requested test. Returns false if it was refused.
+pub fn start(
+    state: &mut types::State,
+    control: &types::Control,
+    test: MotorTest,
+) -> bool {
+    if !allowed(state, control) {
+        return false;
+    }
+    state.motor_test = Some(test);
+    state.motor_test_time_s = 0.;
+    true
+}
+
+// Advances running test, returns outputs to apply.
+// Test is aborted as soon as it is not allowed anymore.
+pub fn update(
+    state: &mut types::State,
+    control: &types::Control,
+) -> Option<Outputs> {
+    let test = state.motor_test?;
+    let time_s = state.motor_test_time_s;
+    state.motor_test_time_s += state.ahrs.dt_s;
+
+    let mut outputs = [0.; MAX_MOTORS];
+    let running = allowed(state, control)
+        && match test {
+            MotorTest::Spin {
+                motor,
+                output,
+                time_s: spin_s,
+            } => {
+                outputs[motor] = output;
+                time_s < spin_s
+            }
+            MotorTest::Calibrate => {
+                if time_s < CALIBRATE_HIGH_S {
+                    outputs = [1.; MAX_MOTORS];
+                }
+                time_s < CALIBRATE_HIGH_S + CALIBRATE_LOW_S
+            }
+            MotorTest::Direction => {
+                let slot_s = DIRECTION_SPIN_S + DIRECTION_PAUSE_S;
+                let motor = (time_s / slot_s) as usize;
+                let motors = control.mixer.geometry.motors.min(MAX_MOTORS);
+                if motor < motors
+                    && libm::fmodf(time_s, slot_s) < DIRECTION_SPIN_S
+                {
+                    outputs[motor] = DIRECTION_OUTPUT;
+                }
+                motor < motors
+            }
+        };
+    if running {
+        Some(outputs)
+    } else {
+        state.motor_test = None;
+        None
+    }
+}
@@ -0,0 +1,113 @@
+// OneShot125, OneShot42 and Multishot ESC protocols.
+// Throttle is pulse width between min and max, pulses are sent once
+// per control loop instead of at fixed rate.
+use crate::utils::clamp;
+
+#[cfg(esc = "esc_oneshot125")]
+pub const PULSE_US: (f32, f32) = (125., 250.);
+#[cfg(esc = "esc_oneshot42")]
+pub const PULSE_US: (f32, f32) = (42., 84.);
+#[cfg(esc = "esc_multishot")]
+pub const PULSE_US: (f32, f32) = (5., 25.);
+
+// resolution exposed to the mixer
+pub const MAX_DUTY: f32 = 2000.;
+
+// Timer period leaves some slack after the longest pulse
+#[inline]
+pub fn period_us(pulse_us: (f32, f32)) -> f32 {
+    pulse_us.1 * 1.1
+}
+
+#[inline]
+pub fn timer_hz(pulse_us: (f32, f32)) -> u32 {
+    (1_000_000. / period_us(pulse_us)) as u32
+}
+
+// Maps duty in [0, MAX_DUTY] to compare value of timer with `period`
+// ticks; zero duty is the shortest pulse, which stops the motor
+#[inline]
+pub fn ticks(duty: f32, pulse_us: (f32, f32), period: u32) -> u32 {
+    let (min, max) = pulse_us;
+    let us = min + clamp(duty / MAX_DUTY, 0., 1.) * (max - min);
+    (us / period_us(pulse_us) * period as f32) as u32
+}
+
+#[cfg(target_os = "none")]
+pub mod stm32f30x {
+    use core::ptr;
+
+    use super::*;
+    use crate::mixer::Outputs;
+
+    const TIM2_BASE: u32 = 0x4000_0000;
+    const TIM3_BASE: u32 = 0x4000_0400;
+    const TIM_EGR: u32 = 0x14;
+    const TIM_EGR_UG: u32 = 1;
+    const TIM_CCMR1: u32 = 0x18;
+    const TIM_CCMR2: u32 = 0x1C;
+    // OCxPE for both channels of CCMR register
+    const TIM_CCMR_PRELOAD: u32 = (1 << 3) | (1 << 11);
+
+    #[inline]
+    unsafe fn modify(address: u32, set: u32) {
+        let value = ptr::read_volatile(address as *const u32);
+        ptr::write_volatile(address as *mut u32, value | set);
+    }
+
+    // Wraps PWM outputs of TIM2 and TIM3.
+    // Compare values are preloaded, so forced update starts a pulse
+    // and zero written right after takes effect only at the end of it.
+    pub struct OneShot<P> {
+        pins: P,
+        period: u32,
+        // shortest and longest pulse of the protocol, us
+        pulse_us: (f32, f32),
+    }
+
+    impl<P: Outputs> OneShot<P> {
+        // Timers have to be configured for PWM at timer_hz(pulse_us)
+        pub fn new(pins: P, pulse_us: (f32, f32)) -> Self {
+            let period = pins.max_duty() as u32;
+            unsafe {
+                for timer in [TIM2_BASE, TIM3_BASE].iter() {
+                    modify(timer + TIM_CCMR1, TIM_CCMR_PRELOAD);
+                    modify(timer + TIM_CCMR2, TIM_CCMR_PRELOAD);
+                }
+            }
+            OneShot {
+                pins,
+                period,
+                pulse_us,
+            }
+        }
+    }
+
+    impl<P: Outputs> Outputs for OneShot<P> {
+        fn count(&self) -> usize {
+            self.pins.count()
+        }
+
+        fn max_duty(&self) -> f32 {
+            MAX_DUTY
+        }
+
+        fn set(&mut self, index: usize, duty: f32) {
+            let ticks = ticks(duty, self.pulse_us, self.period);
+            self.pins.set(index, ticks as f32);
+        }
+
+        fn flush(&mut self) {
+            unsafe {
+                for timer in [TIM2_BASE, TIM3_BASE].iter() {
+                    let egr = (timer + TIM_EGR) as *mut u32;
+                    ptr::write_volatile(egr, TIM_EGR_UG);
+                }
+            }
+            // line stays low until next flush
+            for nr in 0..self.pins.count() {
+                self.pins.set(nr, 0.);
+            }
+        }
+    }
+}
@@ -0,0 +1,123 @@
+// PPM sum signal: channel widths are times between rising edges,
+// frame ends with a gap longer than any channel.
+use crate::rc;
+
+pub const MAX_CHANNELS: usize = 8;
+// timer ticks are microseconds
+const SYNC_US: u16 = 2700;
+// shorter or longer channels are noise, frame is dropped
+const MIN_CHANNEL_US: u16 = 750;
+const MAX_CHANNEL_US: u16 = 2250;
+// frames with fewer channels can not drive attitude and thrust
+const MIN_CHANNELS: usize = 4;
+
+pub struct Ppm {
+    channels: [u16; MAX_CHANNELS],
+    count: usize,
+    // previous rising edge was end of sync gap or of valid channel
+    synced: bool,
+    last_capture: u16,
+}
+
+pub const fn create() -> Ppm {
+    Ppm::new()
+}
+
+impl Ppm {
+    #[inline]
+    pub const fn new() -> Self {
+        Ppm {
+            channels: [rc::CENTER_US as u16; MAX_CHANNELS],
+            count: 0,
+            synced: false,
+            last_capture: 0,
+        }
+    }
+
+    // Takes timer value captured at rising edge, returns input once
+    // sync gap ends a complete frame
+    pub fn feed(&mut self, capture: u16) -> Option<rc::Input> {
+        let width = capture.wrapping_sub(self.last_capture);
+        self.last_capture = capture;
+        if width >= SYNC_US {
+            let complete = self.synced && self.count >= MIN_CHANNELS;
+            let count = self.count;
+            self.synced = true;
+            self.count = 0;
+            if !complete {
+                return None;
+            }
+            let mut input = rc::Input::new();
+            input.channels[..count].copy_from_slice(&self.channels[..count]);
+            input.count = count;
+            return Some(input);
+        }
+        if !self.synced {
+            return None;
+        }
+        if !(MIN_CHANNEL_US..=MAX_CHANNEL_US).contains(&width)
+            || self.count >= MAX_CHANNELS
+        {
+            self.synced = false;
+            return None;
+        }
+        self.channels[self.count] = width;
+        self.count += 1;
+        None
+    }
+}
+
+#[cfg(target_os = "none")]
+pub mod stm32f30x {
+    use hal::pac::{GPIOB, RCC, TIM4};
+
+    const RCC_APB1ENR_TIM4EN: u32 = 1 << 2;
+    // pb6 is TIM4_CH1 in alternate function 2
+    const PIN: u32 = 6;
+    const AF: u32 = 2;
+    // CC1 is input mapped on TI1, filtered over 8 samples
+    const CCMR1_CC1S_TI1: u32 = 0b01;
+    const CCMR1_IC1F: u32 = 0b0011 << 4;
+    // rising edge
+    const CCER_CC1E: u32 = 1;
+    const DIER_CC1IE: u32 = 1 << 1;
+    const SR_CC1IF: u32 = 1 << 1;
+    const CR1_CEN: u32 = 1;
+
+    // Starts TIM4 at 1MHz capturing rising edges on pb6.
+    // APB1 is prescaled, so timer clock is twice pclk1.
+    pub fn configure<P>(pin: P, clocks: hal::rcc::Clocks) {
+        let rcc = unsafe { &*RCC::ptr() };
+        let gpiob = unsafe { &*GPIOB::ptr() };
+        let tim = unsafe { &*TIM4::ptr() };
+        (*rcc)
+            .apb1enr
+            .modify(|r, w| unsafe { w.bits(r.bits() | RCC_APB1ENR_TIM4EN) });
+        (*gpiob).moder.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(0b11 << (PIN * 2)) | (0b10 << (PIN * 2)))
+        });
+        (*gpiob).afrl.modify(|r, w| unsafe {
+            w.bits(r.bits() & !(0b1111 << (PIN * 4)) | (AF << (PIN * 4)))
+        });
+        let timer_hz = clocks.pclk1().0 * 2;
+        (*tim)
+            .psc
+            .write(|w| unsafe { w.bits(timer_hz / 1_000_000 - 1) });
+        (*tim).arr.write(|w| unsafe { w.bits(0xFFFF) });
+        (*tim)
+            .ccmr1_input()
+            .write(|w| unsafe { w.bits(CCMR1_CC1S_TI1 | CCMR1_IC1F) });
+        (*tim).ccer.write(|w| unsafe { w.bits(CCER_CC1E) });
+        (*tim).dier.write(|w| unsafe { w.bits(DIER_CC1IE) });
+        (*tim).cr1.write(|w| unsafe { w.bits(CR1_CEN) });
+    }
+
+    // Returns captured timer value; reading it clears the interrupt
+    pub fn read() -> Option<u16> {
+        let tim = unsafe { &*TIM4::ptr() };
+        if (*tim).sr.read().bits() & SR_CC1IF == 0 {
+            return None;
+        }
+        Some((*tim).ccr1.read().bits() as u16)
+    }
+}
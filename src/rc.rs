@@ -0,0 +1,251 @@
+use crate::types;
+use crate::utils::clamp;
+
+pub const MAX_CHANNELS: usize = 16;
+// channel values are pulse widths in microseconds
+pub const MIN_US: f32 = 1000.0;
+pub const CENTER_US: f32 = 1500.0;
+pub const MAX_US: f32 = 2000.0;
+
+// Channels decoded by receiver protocol
+#[derive(Copy, Clone)]
+pub struct Input {
+    pub channels: [u16; MAX_CHANNELS],
+    pub count: usize,
+    // receiver lost transmitter and sends its own failsafe values
+    pub failsafe: bool,
+    // receiver missed a frame, channels hold previous values
+    pub frame_lost: bool,
+    // percent of uplink frames received, 100 if not reported
+    pub link_quality: u8,
+    // control loop count when input came with rc command,
+    // None for receiver input
+    pub virtual_at: Option<u32>,
+}
+
+impl Input {
+    #[inline]
+    pub const fn new() -> Self {
+        Input {
+            channels: [CENTER_US as u16; MAX_CHANNELS],
+            count: 0,
+            failsafe: false,
+            frame_lost: false,
+            link_quality: 100,
+            virtual_at: None,
+        }
+    }
+}
+
+// Order of roll, pitch, thrust and yaw in receiver channels
+#[derive(Copy, Clone, PartialEq)]
+pub enum ChannelMap {
+    Aetr,
+    Taer,
+}
+
+impl ChannelMap {
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChannelMap::Aetr => "aetr",
+            ChannelMap::Taer => "taer",
+        }
+    }
+
+    // receiver channels of roll, pitch, thrust and yaw
+    #[inline]
+    pub fn indices(&self) -> [usize; 4] {
+        match self {
+            ChannelMap::Aetr => [0, 1, 2, 3],
+            ChannelMap::Taer => [1, 2, 0, 3],
+        }
+    }
+}
+
+// Stick endpoints and center as sent by receiver, microseconds
+#[derive(Copy, Clone)]
+pub struct Calibration {
+    pub min: f32,
+    pub mid: f32,
+    pub max: f32,
+}
+
+impl Calibration {
+    #[inline]
+    pub const fn new() -> Self {
+        Calibration {
+            min: MIN_US,
+            mid: CENTER_US,
+            max: MAX_US,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Config {
+    pub map: ChannelMap,
+    // per receiver channel, first four channels carry sticks
+    pub calibration: [Calibration; 4],
+    // microseconds around center ignored on roll, pitch and yaw
+    pub deadband: f32,
+    // roll, pitch, yaw: 0 is linear, 1 is cubic
+    pub expo: [f32; 3],
+    // roll, pitch, yaw target rates (degrees per second) at full stick
+    pub rates: [f32; 3],
+    // roll and pitch target (degrees) at full stick in angle mode
+    pub max_angle: f32,
+    // thrust at full throttle stick
+    pub max_thrust: f32,
+    // frames with worse link quality (percent) do not keep link alive
+    pub min_link_quality: u8,
+}
+
+impl Config {
+    #[inline]
+    pub const fn new() -> Self {
+        Config {
+            map: ChannelMap::Aetr,
+            calibration: [Calibration::new(); 4],
+            deadband: 5.0,
+            expo: [0.0; 3],
+            rates: [200.0; 3],
+            max_angle: 30.0,
+            max_thrust: 2000.0,
+            min_link_quality: 10,
+        }
+    }
+}
+
+// Targets produced from sticks
+#[derive(Copy, Clone)]
+pub struct Setpoints {
+    // roll, pitch, yaw, degrees per second
+    pub rates: [f32; 3],
+    // roll, pitch, degrees
+    pub degrees: [f32; 2],
+    pub thrust: f32,
+}
+
+#[cfg(receiver = "receiver_sbus")]
+pub type Parser = crate::sbus::Sbus;
+#[cfg(receiver = "receiver_crsf")]
+pub type Parser = crate::crsf::Crsf;
+
+#[cfg(receiver = "receiver_sbus")]
+pub const BAUD: u32 = 100_000;
+#[cfg(receiver = "receiver_crsf")]
+pub const BAUD: u32 = 420_000;
+
+// Unpacks 11 bit channels, LSB first, as sent by SBUS and CRSF.
+// 172..1811 maps to 988..2012us, as most receivers do.
+pub fn unpack(data: &[u8], input: &mut Input) {
+    let mut bits = 0u32;
+    let mut count = 0;
+    let mut channel = 0;
+    for byte in data.iter() {
+        bits |= (*byte as u32) << count;
+        count += 8;
+        if count >= 11 && channel < MAX_CHANNELS {
+            input.channels[channel] = ((bits & 0x07FF) * 5 / 8 + 880) as u16;
+            bits >>= 11;
+            count -= 11;
+            channel += 1;
+        }
+    }
+    input.count = channel;
+}
+
+// Stick as -1..1 around calibrated center, deadband is cut out
+// so that output still starts from zero right outside of it
+pub fn normalize(us: f32, calibration: &Calibration, deadband: f32) -> f32 {
+    let offset = us - calibration.mid;
+    let range = if offset >= 0. {
+        calibration.max - calibration.mid
+    } else {
+        calibration.mid - calibration.min
+    };
+    if range <= deadband {
+        return 0.;
+    }
+    let magnitude =
+        (libm::fabsf(offset) - deadband).max(0.) / (range - deadband);
+    clamp(libm::copysignf(magnitude, offset), -1., 1.)
+}
+
+// Throttle stick as 0..1 between calibrated endpoints
+pub fn throttle(us: f32, calibration: &Calibration) -> f32 {
+    let range = calibration.max - calibration.min;
+    if range <= 0. {
+        return 0.;
+    }
+    clamp((us - calibration.min) / range, 0., 1.)
+}
+
+// Blends linear and cubic response, softening center of stick
+#[inline]
+pub fn expo(stick: f32, expo: f32) -> f32 {
+    stick * (1. - expo) + stick * stick * stick * expo
+}
+
+pub fn process(input: &Input, config: &Config) -> Setpoints {
+    let [roll_ch, pitch_ch, thrust_ch, yaw_ch] = config.map.indices();
+    let stick = |channel: usize, axis: usize| {
+        let us = input.channels[channel] as f32;
+        let calibration = &config.calibration[channel];
+        let value = normalize(us, calibration, config.deadband);
+        expo(value, config.expo[axis])
+    };
+    let sticks = [stick(roll_ch, 0), stick(pitch_ch, 1), stick(yaw_ch, 2)];
+    let thrust_us = input.channels[thrust_ch] as f32;
+    Setpoints {
+        rates: [
+            sticks[0] * config.rates[0],
+            sticks[1] * config.rates[1],
+            sticks[2] * config.rates[2],
+        ],
+        degrees: [sticks[0] * config.max_angle, sticks[1] * config.max_angle],
+        thrust: throttle(thrust_us, &config.calibration[thrust_ch])
+            * config.max_thrust,
+    }
+}
+
+// Takes new receiver input. Frames without receiver failsafe count
+// as link activity and set targets.
+pub fn update(input: Input, control: &mut types::Control) {
+    control.rc_input = input;
+    if input.failsafe
+        || input.count < 4
+        || input.link_quality < control.rc.min_link_quality
+    {
+        return;
+    }
+    control.link_seq = control.link_seq.wrapping_add(1);
+    let setpoints = process(&input, &control.rc);
+    control.target_degrees.roll = setpoints.degrees[0];
+    control.target_degrees.pitch = setpoints.degrees[1];
+    control.target_rates.roll = setpoints.rates[0];
+    control.target_rates.pitch = setpoints.rates[1];
+    control.target_rates.yaw = setpoints.rates[2];
+    control.thrust = setpoints.thrust;
+}
+
+// Zeroes targets and thrust once virtual input is older than link
+// timeout; other commands may keep link alive meanwhile.
+pub fn expire(state: &types::State, control: &mut types::Control) {
+    let received = match control.rc_input.virtual_at {
+        Some(loops) => loops,
+        None => return,
+    };
+    let age_s = state.loops.wrapping_sub(received) as f32 * state.ahrs.dt_s;
+    if control.link_timeout <= 0. || age_s <= control.link_timeout {
+        return;
+    }
+    control.rc_input.virtual_at = None;
+    control.target_degrees.roll = 0.;
+    control.target_degrees.pitch = 0.;
+    control.target_rates.roll = 0.;
+    control.target_rates.pitch = 0.;
+    control.target_rates.yaw = 0.;
+    control.thrust = 0.;
+}
@@ -0,0 +1,90 @@
+// SBUS receiver protocol: 100000 baud, 8E2, inverted line.
+// Frame is header, 16 channels of 11 bits packed LSB first, flags
+// and footer, 25 bytes in total.
+use crate::rc;
+
+const FRAME_LEN: usize = 25;
+const HEADER: u8 = 0x0F;
+const CHANNELS: usize = 16;
+const DATA_LEN: usize = CHANNELS * 11 / 8;
+const FLAG_FRAME_LOST: u8 = 1 << 2;
+const FLAG_FAILSAFE: u8 = 1 << 3;
+
+pub struct Sbus {
+    buffer: [u8; FRAME_LEN],
+    pos: usize,
+}
+
+pub const fn create() -> Sbus {
+    Sbus::new()
+}
+
+impl Sbus {
+    #[inline]
+    pub const fn new() -> Self {
+        Sbus {
+            buffer: [0; FRAME_LEN],
+            pos: 0,
+        }
+    }
+
+    // drops partial frame, e.g. after line error
+    #[inline]
+    pub fn reset(&mut self) {
+        self.pos = 0;
+    }
+
+    // Returns input once complete frame is received
+    pub fn feed(&mut self, byte: u8) -> Option<rc::Input> {
+        if self.pos == 0 && byte != HEADER {
+            return None;
+        }
+        self.buffer[self.pos] = byte;
+        self.pos += 1;
+        if self.pos < FRAME_LEN {
+            return None;
+        }
+        self.pos = 0;
+        // SBUS2 receivers cycle footers for telemetry slots
+        let footer = self.buffer[FRAME_LEN - 1];
+        if footer != 0x00 && footer & 0x0F != 0x04 {
+            return None;
+        }
+        Some(decode(&self.buffer))
+    }
+}
+
+pub fn decode(frame: &[u8; FRAME_LEN]) -> rc::Input {
+    let mut input = rc::Input::new();
+    rc::unpack(&frame[1..=DATA_LEN], &mut input);
+    let flags = frame[DATA_LEN + 1];
+    input.frame_lost = flags & FLAG_FRAME_LOST != 0;
+    input.failsafe = flags & FLAG_FAILSAFE != 0;
+    input
+}
+
+#[cfg(target_os = "none")]
+pub mod stm32f30x {
+    use hal::pac::USART1;
+
+    const CR1_UE: u32 = 1;
+    const CR1_PCE: u32 = 1 << 10;
+    const CR1_M0: u32 = 1 << 12;
+    const CR2_STOP_2: u32 = 0b10 << 12;
+    const CR2_RXINV: u32 = 1 << 16;
+
+    // Switches USART1, already set up for 100000 baud, to 8E2 with
+    // inverted receive line: 9 bit words include parity.
+    pub fn configure() {
+        let usart = unsafe { &*USART1::ptr() };
+        (*usart)
+            .cr1
+            .modify(|r, w| unsafe { w.bits(r.bits() & !CR1_UE) });
+        (*usart).cr2.modify(|r, w| unsafe {
+            w.bits(r.bits() | CR2_STOP_2 | CR2_RXINV)
+        });
+        (*usart).cr1.modify(|r, w| unsafe {
+            w.bits(r.bits() | CR1_M0 | CR1_PCE | CR1_UE)
+        });
+    }
+}
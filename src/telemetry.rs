@@ -1,3 +1,4 @@
+use crate::cmd;
 use crate::communication::{Channel, TxBuffer};
 use crate::types;
 
@@ -12,11 +13,22 @@ impl Telemetry {
     #[inline]
     pub fn state(&self, state: &types::State, channel: Channel) -> Channel {
         channel.send(|buffer| {
-            // tm:ax,ay,az,gx,gy,gz,dt_s,y,p,r,cx,cy,cz
+            // tm:ax,ay,az,gx,gy,gz,dt_s,y,p,r,cx,cy,cz,volts,amps,mah
             buffer.push(b't');
             buffer.push(b'm');
             buffer.push(b':');
-            for f in state.ahrs.short_results().iter().chain(state.cmd.iter()) {
+            let battery = [
+                state.battery.voltage,
+                state.battery.current,
+                state.battery.mah,
+            ];
+            for f in state
+                .ahrs
+                .short_results()
+                .iter()
+                .chain(state.cmd.iter())
+                .chain(battery.iter())
+            {
                 let mut b = ryu::Buffer::new();
                 let s = b.format(*f);
                 buffer.extend_from_slice(s.as_bytes());
@@ -26,14 +38,57 @@ impl Telemetry {
         })
     }
 
+    // Returns false if channel was busy and phase has to be retried
     #[inline]
-    pub fn control(
+    pub fn failsafe(
+        &self,
+        phase: types::Failsafe,
+        channel: Channel,
+    ) -> (Channel, bool) {
+        channel.try_send(|buffer| {
+            // fs:phase
+            buffer.push(b'f');
+            buffer.push(b's');
+            buffer.push(b':');
+            buffer.extend_from_slice(phase.as_str().as_bytes());
+            buffer.push(b'\n');
+        })
+    }
+
+    // Returns false if channel was busy and reply has to be retried
+    #[inline]
+    pub fn reply(
+        &self,
+        reply: &cmd::Reply,
+        channel: Channel,
+    ) -> (Channel, bool) {
+        channel.try_send(|buffer| {
+            // ok:command or er:reason;command
+            match reply.result {
+                Ok(()) => buffer.extend_from_slice(b"ok:"),
+                Err(e) => {
+                    buffer.extend_from_slice(b"er:");
+                    buffer.extend_from_slice(e.as_str().as_bytes());
+                    buffer.push(b';');
+                }
+            }
+            buffer.extend_from_slice(&reply.echo);
+            buffer.push(b'\n');
+        })
+    }
+
+    #[inline]
+    pub fn status(
         &self,
         control: &types::Control,
+        state: &types::State,
         channel: Channel,
     ) -> Channel {
         channel.send(|buffer| {
-            // ct:pk,ik,dk,pitch_pk,roll_pk,yaw_pk;
+            // ct:xp,xi,xd,xff,yp,yi,yd,yff,zp,zi,zd,zff,
+            //    pitch_pk,roll_pk,yaw_pk,max_rate,horizon_transition,
+            //    dterm_filter,dterm_cutoff,i_limit,i_thrust,mode,airmode,
+            //    thrust_linear,idle,max_output;
             buffer.push(b'c');
             buffer.push(b't');
             buffer.push(b':');
@@ -44,6 +99,49 @@ impl Telemetry {
                 buffer.push(b';');
             }
             buffer.push(b'\n');
+            // st:armed|disarmed,arm_refusal|none,failsafe,battery,
+            //    wdg|nowdg,motor_test|none,overruns,missed,busy_s,
+            //    imu_errors,saturated,cells,link_quality;
+            buffer.push(b's');
+            buffer.push(b't');
+            buffer.push(b':');
+            if state.armed {
+                buffer.extend_from_slice(b"armed;");
+            } else {
+                buffer.extend_from_slice(b"disarmed;");
+            }
+            let refusal = state.arm_refusal.map_or("none", |r| r.as_str());
+            buffer.extend_from_slice(refusal.as_bytes());
+            buffer.push(b';');
+            buffer.extend_from_slice(state.failsafe.as_str().as_bytes());
+            buffer.push(b';');
+            let battery = state.battery.status.as_str();
+            buffer.extend_from_slice(battery.as_bytes());
+            buffer.push(b';');
+            if state.watchdog_reset {
+                buffer.extend_from_slice(b"wdg;");
+            } else {
+                buffer.extend_from_slice(b"nowdg;");
+            }
+            let test = state.motor_test.map_or("none", |t| t.as_str());
+            buffer.extend_from_slice(test.as_bytes());
+            buffer.push(b';');
+            let counters = [
+                state.overruns as f32,
+                state.missed as f32,
+                state.busy_s,
+                state.imu_errors as f32,
+                state.saturated as u8 as f32,
+                state.battery.cells as f32,
+                control.rc_input.link_quality as f32,
+            ];
+            for f in counters.iter() {
+                let mut b = ryu::Buffer::new();
+                let s = b.format(*f);
+                buffer.extend_from_slice(s.as_bytes());
+                buffer.push(b';');
+            }
+            buffer.push(b'\n');
         })
     }
 }
@@ -1,11 +1,54 @@
 use crate::ahrs::AhrsResult;
+use crate::battery;
+use crate::dshot;
+use crate::filters;
+use crate::mixer;
+use crate::modes;
 use crate::prelude::*;
+use crate::rc;
 
 #[derive(Copy, Clone)]
 pub struct State {
     pub ahrs: AhrsResult,
     pub cmd: [f32; 3],
     pub errors: [f32; 3],
+    pub integrals: [f32; 3],
+    pub dterm_filters: [filters::LowPass; 3],
+    // filtered rates from previous step
+    pub dterm_rates: [f32; 3],
+    pub armed: bool,
+    // reason of last refused arming request
+    pub arm_refusal: Option<ArmRefusal>,
+    // consecutive failed estimations
+    pub imu_errors: u32,
+    // mixer geometry fits motor outputs
+    pub mixer_ok: bool,
+    // mixer could not satisfy last corrections
+    pub saturated: bool,
+    // command link tracking, see failsafe
+    pub link_seq: u32,
+    pub link_age_s: f32,
+    pub failsafe: Failsafe,
+    // time spent in current failsafe phase
+    pub failsafe_time_s: f32,
+    // thrust at the moment of link loss
+    pub failsafe_thrust: f32,
+    // completed control loops, watchdog is fed while it grows
+    pub loops: u32,
+    // time last control loop took to complete
+    pub busy_s: f32,
+    // loops that took longer than sampling period
+    pub overruns: u32,
+    // sensor interrupts that never came
+    pub missed: u32,
+    // last reset was caused by watchdog
+    pub watchdog_reset: bool,
+    // electrical RPM reported by ESCs, see dshot
+    pub erpm: [f32; mixer::MAX_MOTORS],
+    // running bench test and time since its start
+    pub motor_test: Option<MotorTest>,
+    pub motor_test_time_s: f32,
+    pub battery: battery::Battery,
 }
 
 impl State {
@@ -15,6 +58,151 @@ impl State {
             ahrs: AhrsResult::new(),
             cmd: [0.0, 0.0, 0.0],
             errors: [0.0, 0.0, 0.0],
+            integrals: [0.0, 0.0, 0.0],
+            dterm_filters: [
+                filters::LowPass::new(),
+                filters::LowPass::new(),
+                filters::LowPass::new(),
+            ],
+            dterm_rates: [0.0, 0.0, 0.0],
+            armed: false,
+            arm_refusal: None,
+            imu_errors: 0,
+            mixer_ok: false,
+            saturated: false,
+            link_seq: 0,
+            link_age_s: 0.0,
+            failsafe: Failsafe::Ok,
+            failsafe_time_s: 0.0,
+            failsafe_thrust: 0.0,
+            loops: 0,
+            busy_s: 0.0,
+            overruns: 0,
+            missed: 0,
+            watchdog_reset: false,
+            erpm: [0.0; mixer::MAX_MOTORS],
+            motor_test: None,
+            motor_test_time_s: 0.0,
+            battery: battery::Battery::new(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ArmRefusal {
+    Thrust,
+    Attitude,
+    Imu,
+    Mixer,
+    MotorTest,
+    Battery,
+}
+
+impl ArmRefusal {
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArmRefusal::Thrust => "thrust",
+            ArmRefusal::Attitude => "attitude",
+            ArmRefusal::Imu => "imu",
+            ArmRefusal::Mixer => "mixer",
+            ArmRefusal::MotorTest => "motortest",
+            ArmRefusal::Battery => "battery",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Failsafe {
+    Ok,
+    // link is lost, holding level attitude
+    Level,
+    // ramping thrust down
+    Descend,
+    // thrust is zero, disarmed
+    Landed,
+}
+
+impl Failsafe {
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Failsafe::Ok => "ok",
+            Failsafe::Level => "level",
+            Failsafe::Descend => "descend",
+            Failsafe::Landed => "landed",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum MotorTest {
+    // single motor at given output (fraction of max) for given time
+    Spin {
+        motor: usize,
+        output: f32,
+        time_s: f32,
+    },
+    // max then min output of all motors, ESCs learn endpoints
+    Calibrate,
+    // every motor in turn at low output
+    Direction,
+}
+
+impl MotorTest {
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MotorTest::Spin { .. } => "spin",
+            MotorTest::Calibrate => "calibrate",
+            MotorTest::Direction => "direction",
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Gains {
+    pub p: f32,
+    pub i: f32,
+    pub d: f32,
+    // feed-forward from rate setpoint
+    pub ff: f32,
+}
+
+impl Gains {
+    #[inline]
+    pub const fn new() -> Self {
+        Gains {
+            p: 0.0,
+            i: 0.0,
+            d: 0.0,
+            ff: 0.0,
+        }
+    }
+
+    #[inline]
+    pub fn coefficients(&self) -> [f32; 4] {
+        [self.p, self.i, self.d, self.ff]
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum FlightMode {
+    // rate only
+    Acro,
+    // self-level, roll and pitch targets are angles
+    Angle,
+    // rate, with self-level fading out with tilt
+    Horizon,
+}
+
+impl FlightMode {
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FlightMode::Acro => "acro",
+            FlightMode::Angle => "angle",
+            FlightMode::Horizon => "horizon",
         }
     }
 }
@@ -23,16 +211,68 @@ impl State {
 pub struct Control {
     // permanent part
     pub telemetry: bool,
-    pub pk: f32,
-    pub ik: f32,
-    pub dk: f32,
+    // arming request, may be dropped by pre-arm checks
+    pub arm: bool,
+    // max thrust and tilt (degrees) allowed for arming
+    pub arm_thrust: f32,
+    pub arm_max_tilt: f32,
+    // incremented on every valid command
+    pub link_seq: u32,
+    // seconds without commands before failsafe kicks in, 0 disables it
+    pub link_timeout: f32,
+    // seconds to hold level before descending
+    pub fs_level_time: f32,
+    // seconds to ramp thrust down to zero
+    pub fs_descent_time: f32,
+    // battery sensing and low voltage thresholds
+    pub battery: battery::Config,
+    pub mode: FlightMode,
+    pub mixer: mixer::Config,
+    // pending special ESC command, only sent while disarmed
+    pub esc_command: Option<dshot::Command>,
+    // user confirmed props are removed, enables motor tests
+    pub props_off: bool,
+    // pending motor test request, see motortest
+    pub motor_test: Option<MotorTest>,
+    // rate loop gains: x (roll), y (pitch), z (yaw)
+    pub gains: [Gains; 3],
     pub pitch_pk: f32,
     pub roll_pk: f32,
     pub yaw_pk: f32,
+    // max rate (degrees per second) requested by angle loop
+    pub max_rate: f32,
+    // tilt (degrees) at which horizon mode stops self-leveling
+    pub horizon_transition: f32,
+    // gyro notches following motor RPM: motor poles, notch Q and
+    // lowest motor frequency (Hz) that is filtered; notches of motors
+    // above half of loop rate follow their aliases
+    pub motor_poles: f32,
+    pub rpm_q: f32,
+    pub rpm_min_hz: f32,
+    // low-pass applied to measured rates before D-term
+    pub dterm_filter: filters::Kind,
+    pub dterm_cutoff: f32,
+    // max absolute value of integral part of correction
+    pub i_limit: f32,
+    // integrals are reset while thrust is below this value
+    pub i_thrust: f32,
+    // receiver input and its mapping to thrust and targets
+    pub rc_input: rc::Input,
+    pub rc: rc::Config,
+    // AUX channel windows switching actions, see modes
+    pub ranges: modes::Ranges,
+    // actions switched on at last receiver input
+    pub switches: modes::Switches,
+    // ranges left at least once since configured
+    pub seen_off: modes::Latches,
+    // pilot requested failsafe as if link was lost
+    pub failsafe_switch: bool,
     pub thrust: f32,
     // temp, as this has to be controlled from top level ctrl
     // XXX: units via naming? foooo...
     pub target_degrees: EulerAngles,
+    // degrees per second
+    pub target_rates: EulerAngles,
 }
 
 impl Control {
@@ -40,34 +280,88 @@ impl Control {
     pub const fn new() -> Self {
         Control {
             telemetry: false,
-            pk: 0.0,
-            ik: 0.0,
-            dk: 0.0,
+            arm: false,
+            arm_thrust: 50.0,
+            arm_max_tilt: 25.0,
+            link_seq: 0,
+            link_timeout: 1.0,
+            fs_level_time: 1.0,
+            fs_descent_time: 5.0,
+            battery: battery::Config::new(),
+            mode: FlightMode::Angle,
+            mixer: mixer::Config::new(),
+            esc_command: None,
+            props_off: false,
+            motor_test: None,
+            gains: [Gains::new(), Gains::new(), Gains::new()],
             pitch_pk: 0.0,
             roll_pk: 0.0,
             yaw_pk: 0.0,
+            max_rate: 200.0,
+            horizon_transition: 75.0,
+            motor_poles: 14.0,
+            rpm_q: 5.0,
+            rpm_min_hz: 100.0,
+            dterm_filter: filters::Kind::Pt1,
+            dterm_cutoff: 50.0,
+            i_limit: 100.0,
+            i_thrust: 100.0,
+            rc_input: rc::Input::new(),
+            rc: rc::Config::new(),
+            ranges: [None; modes::MAX_RANGES],
+            switches: [false; modes::ACTIONS],
+            seen_off: [false; modes::MAX_RANGES],
+            failsafe_switch: false,
             thrust: 0.0,
             target_degrees: EulerAngles {
                 yaw: 0.0,
                 pitch: 0.0,
                 roll: 0.0,
             },
+            target_rates: EulerAngles {
+                yaw: 0.0,
+                pitch: 0.0,
+                roll: 0.0,
+            },
         }
     }
 
     #[inline]
-    pub fn coefficients(&self) -> [f32; 6] {
+    pub fn coefficients(&self) -> [f32; 26] {
+        let [x, y, z] = self.gains;
+        let (x, y, z) = (x.coefficients(), y.coefficients(), z.coefficients());
         [
-            self.pk,
-            self.ik,
-            self.dk,
+            x[0],
+            x[1],
+            x[2],
+            x[3],
+            y[0],
+            y[1],
+            y[2],
+            y[3],
+            z[0],
+            z[1],
+            z[2],
+            z[3],
             self.pitch_pk,
             self.roll_pk,
             self.yaw_pk,
+            self.max_rate,
+            self.horizon_transition,
+            self.dterm_filter as u8 as f32,
+            self.dterm_cutoff,
+            self.i_limit,
+            self.i_thrust,
+            self.mode as u8 as f32,
+            self.mixer.airmode as u8 as f32,
+            self.mixer.thrust_linear,
+            self.mixer.idle,
+            self.mixer.max_output,
         ]
     }
 }
 
+#[derive(Copy, Clone)]
 pub enum Requests {
     Status,
     Reset,
@@ -12,3 +12,16 @@ pub fn fill_with_str(buffer: &mut TxBuffer, arg: &str) {
 pub fn to_rads(d: f32) -> f32 {
     d * PI / 180.
 }
+
+#[inline]
+pub fn clamp<T: PartialOrd>(val: T, min: T, max: T) -> T {
+    if val > min {
+        if val < max {
+            val
+        } else {
+            max
+        }
+    } else {
+        min
+    }
+}
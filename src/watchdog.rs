@@ -0,0 +1,93 @@
+use crate::types;
+
+pub trait Watchdog {
+    fn start(&mut self, timeout_ms: u32);
+    fn feed(&mut self);
+    /// Returns true if last reset was caused by watchdog; clears reset flags
+    fn was_reset(&mut self) -> bool;
+}
+
+pub type T = impl Watchdog;
+
+#[inline]
+pub const fn create() -> T {
+    stm32f30x::Watchdog::new()
+}
+
+// Accounts control loop timings: `dt_s` is time between two loops,
+// `busy_s` is time loop took to complete.
+pub fn account(
+    state: &mut types::State,
+    dt_s: f32,
+    busy_s: f32,
+    period_s: f32,
+) {
+    state.loops = state.loops.wrapping_add(1);
+    state.busy_s = busy_s;
+    if busy_s > period_s {
+        state.overruns += 1;
+    }
+    // some jitter is fine, whole periods are not
+    if dt_s > period_s * 1.5 {
+        state.missed += libm::roundf(dt_s / period_s) as u32 - 1;
+    }
+}
+
+pub mod stm32f30x {
+    use hal::pac::{IWDG, RCC};
+
+    use super::Watchdog as WatchdogTrait;
+
+    const KEY_RELOAD: u32 = 0xAAAA;
+    const KEY_START: u32 = 0xCCCC;
+    const KEY_ACCESS: u32 = 0x5555;
+    // LSI is ~40kHz, with /32 prescaler one tick is 0.8ms
+    const PRESCALER_DIV32: u32 = 0b011;
+    const MAX_RELOAD: u32 = 0x0FFF;
+    const RCC_CSR_RMVF: u32 = 1 << 24;
+    const RCC_CSR_IWDGRSTF: u32 = 1 << 29;
+
+    pub struct Watchdog;
+
+    impl Watchdog {
+        #[inline]
+        pub const fn new() -> Self {
+            Watchdog {}
+        }
+
+        fn key(&mut self, key: u32) {
+            let iwdg = unsafe { &*IWDG::ptr() };
+            (*iwdg).kr.write(|w| unsafe { w.bits(key) });
+        }
+    }
+
+    impl WatchdogTrait for Watchdog {
+        fn start(&mut self, timeout_ms: u32) {
+            let iwdg = unsafe { &*IWDG::ptr() };
+            let reload = (timeout_ms * 5 / 4).min(MAX_RELOAD);
+            // starting watchdog also starts LSI
+            self.key(KEY_START);
+            self.key(KEY_ACCESS);
+            (*iwdg).pr.write(|w| unsafe { w.bits(PRESCALER_DIV32) });
+            (*iwdg).rlr.write(|w| unsafe { w.bits(reload) });
+            // wait for registers to be updated
+            while (*iwdg).sr.read().bits() != 0 {
+                cortex_m::asm::nop();
+            }
+            self.key(KEY_RELOAD);
+        }
+
+        fn feed(&mut self) {
+            self.key(KEY_RELOAD);
+        }
+
+        fn was_reset(&mut self) -> bool {
+            let rcc = unsafe { &*RCC::ptr() };
+            let csr = (*rcc).csr.read().bits();
+            (*rcc)
+                .csr
+                .modify(|r, w| unsafe { w.bits(r.bits() | RCC_CSR_RMVF) });
+            csr & RCC_CSR_IWDGRSTF != 0
+        }
+    }
+}